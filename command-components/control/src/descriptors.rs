@@ -0,0 +1,196 @@
+use usb_wasm_bindings::{
+    device::UsbDevice,
+    types::{ControlSetup, ControlSetupRecipient, ControlSetupType},
+};
+
+const GET_DESCRIPTOR: u8 = 0x06;
+const DESCRIPTOR_TYPE_DEVICE: u16 = 0x01;
+const DESCRIPTOR_TYPE_CONFIGURATION: u16 = 0x02;
+const DESCRIPTOR_TYPE_STRING: u16 = 0x03;
+const DESCRIPTOR_TYPE_INTERFACE: u8 = 0x04;
+const DESCRIPTOR_TYPE_ENDPOINT: u8 = 0x05;
+
+/// Standard USB device descriptor (USB 2.0 spec table 9-8).
+#[derive(Debug, Clone)]
+pub struct DeviceDescriptor {
+    pub usb_version: u16,
+    pub device_class: u8,
+    pub device_subclass: u8,
+    pub device_protocol: u8,
+    pub max_packet_size_0: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub device_version: u16,
+    pub num_configurations: u8,
+}
+
+/// Standard configuration descriptor plus the interfaces parsed out of the
+/// descriptor run that follows it.
+#[derive(Debug, Clone)]
+pub struct ConfigurationDescriptor {
+    pub total_length: u16,
+    pub num_interfaces: u8,
+    pub configuration_value: u8,
+    pub attributes: u8,
+    pub max_power: u8,
+    pub interfaces: Vec<InterfaceDescriptor>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InterfaceDescriptor {
+    pub interface_number: u8,
+    pub alternate_setting: u8,
+    pub interface_class: u8,
+    pub interface_subclass: u8,
+    pub interface_protocol: u8,
+    pub endpoints: Vec<EndpointDescriptor>,
+    /// Class-specific descriptors (HID, CDC, ...) this module doesn't know
+    /// how to interpret yet, kept as raw `bLength`-prefixed blobs so a
+    /// later HID/CDC parser can pick them up without re-walking the run.
+    pub extra: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EndpointDescriptor {
+    pub endpoint_address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+fn parse_device_descriptor_bytes(data: &[u8]) -> anyhow::Result<DeviceDescriptor> {
+    anyhow::ensure!(data.len() >= 18, "device descriptor too short");
+    Ok(DeviceDescriptor {
+        usb_version: u16::from_le_bytes([data[2], data[3]]),
+        device_class: data[4],
+        device_subclass: data[5],
+        device_protocol: data[6],
+        max_packet_size_0: data[7],
+        vendor_id: u16::from_le_bytes([data[8], data[9]]),
+        product_id: u16::from_le_bytes([data[10], data[11]]),
+        device_version: u16::from_le_bytes([data[12], data[13]]),
+        num_configurations: data[17],
+    })
+}
+
+/// Walks a configuration descriptor's back-to-back descriptor run
+/// (`data[0..9]` is the configuration descriptor itself; what follows is
+/// its interfaces, their endpoints, and any class-specific descriptors),
+/// advancing by each entry's `bLength` rather than assuming fixed offsets.
+fn parse_configuration_descriptor_bytes(data: &[u8]) -> anyhow::Result<ConfigurationDescriptor> {
+    anyhow::ensure!(data.len() >= 9, "configuration descriptor too short");
+    let total_length = u16::from_le_bytes([data[2], data[3]]);
+    let data = &data[..(total_length as usize).min(data.len())];
+
+    let mut interfaces: Vec<InterfaceDescriptor> = Vec::new();
+    let mut offset = data[0] as usize; // skip the configuration descriptor header itself
+
+    while offset + 2 <= data.len() {
+        let length = data[offset] as usize;
+        if length == 0 || offset + length > data.len() {
+            break;
+        }
+        let descriptor_type = data[offset + 1];
+        let body = &data[offset..offset + length];
+
+        match descriptor_type {
+            DESCRIPTOR_TYPE_INTERFACE => {
+                anyhow::ensure!(body.len() >= 9, "interface descriptor too short");
+                interfaces.push(InterfaceDescriptor {
+                    interface_number: body[2],
+                    alternate_setting: body[3],
+                    interface_class: body[5],
+                    interface_subclass: body[6],
+                    interface_protocol: body[7],
+                    endpoints: Vec::new(),
+                    extra: Vec::new(),
+                });
+            }
+            DESCRIPTOR_TYPE_ENDPOINT => {
+                anyhow::ensure!(body.len() >= 7, "endpoint descriptor too short");
+                let endpoint = EndpointDescriptor {
+                    endpoint_address: body[2],
+                    attributes: body[3],
+                    max_packet_size: u16::from_le_bytes([body[4], body[5]]),
+                    interval: body[6],
+                };
+                if let Some(interface) = interfaces.last_mut() {
+                    interface.endpoints.push(endpoint);
+                }
+            }
+            _ => {
+                if let Some(interface) = interfaces.last_mut() {
+                    interface.extra.push(body.to_vec());
+                }
+            }
+        }
+
+        offset += length;
+    }
+
+    Ok(ConfigurationDescriptor {
+        total_length,
+        num_interfaces: data[4],
+        configuration_value: data[5],
+        attributes: data[7],
+        max_power: data[8],
+        interfaces,
+    })
+}
+
+fn decode_string_descriptor_bytes(data: &[u8]) -> String {
+    if data.len() < 2 {
+        return String::new();
+    }
+    let length = (data[0] as usize).min(data.len());
+    let utf16_units: Vec<u16> = data[2..length]
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    String::from_utf16_lossy(&utf16_units)
+}
+
+fn get_descriptor(device: &UsbDevice, descriptor_type: u16, index: u8, lang_id: u16) -> Vec<u8> {
+    device.read_control(ControlSetup {
+        request_type: ControlSetupType::Standard,
+        request_recipient: ControlSetupRecipient::Device,
+        request: GET_DESCRIPTOR,
+        value: (descriptor_type << 8) | index as u16,
+        index: lang_id,
+    })
+}
+
+/// Reads string descriptor `index` using the device's first supported
+/// LANGID (string descriptor 0's first entry), decoding the UTF-16LE body.
+pub fn read_string_descriptor(device: &UsbDevice, index: u8) -> anyhow::Result<String> {
+    if index == 0 {
+        return Ok(String::new());
+    }
+
+    let langids = get_descriptor(device, DESCRIPTOR_TYPE_STRING, 0, 0);
+    anyhow::ensure!(langids.len() >= 4, "device reported no supported LANGIDs");
+    let lang_id = u16::from_le_bytes([langids[2], langids[3]]);
+
+    let raw = get_descriptor(device, DESCRIPTOR_TYPE_STRING, index, lang_id);
+    Ok(decode_string_descriptor_bytes(&raw))
+}
+
+/// Extension methods that wrap the raw `GET_DESCRIPTOR` control-IN calls a
+/// guest would otherwise have to hand-assemble, parsing the result into
+/// [`DeviceDescriptor`] / [`ConfigurationDescriptor`].
+pub trait UsbDeviceDescriptorExt {
+    fn parse_device_descriptor(&self) -> anyhow::Result<DeviceDescriptor>;
+    fn parse_configuration_descriptor(&self, index: u8) -> anyhow::Result<ConfigurationDescriptor>;
+}
+
+impl UsbDeviceDescriptorExt for UsbDevice {
+    fn parse_device_descriptor(&self) -> anyhow::Result<DeviceDescriptor> {
+        let data = get_descriptor(self, DESCRIPTOR_TYPE_DEVICE, 0, 0);
+        parse_device_descriptor_bytes(&data)
+    }
+
+    fn parse_configuration_descriptor(&self, index: u8) -> anyhow::Result<ConfigurationDescriptor> {
+        let data = get_descriptor(self, DESCRIPTOR_TYPE_CONFIGURATION, index, 0);
+        parse_configuration_descriptor_bytes(&data)
+    }
+}
@@ -1,10 +1,11 @@
-use usb_wasm_bindings::{
-    device::UsbDevice,
-    types::{ControlSetup, ControlSetupRecipient, ControlSetupType, Filter},
-};
+mod descriptors;
+
+use usb_wasm_bindings::{device::UsbDevice, types::Filter};
 
 use anyhow::anyhow;
 
+use descriptors::UsbDeviceDescriptorExt;
+
 pub fn main() -> anyhow::Result<()> {
     let arduino_usb = UsbDevice::request_device(&Filter {
         vendor_id: Some(0x2341),
@@ -17,15 +18,11 @@ pub fn main() -> anyhow::Result<()> {
     arduino_usb.open();
 
     // GET_DESCRIPTOR request https://www.beyondlogic.org/usbnutshell/usb6.shtml
-    let response = arduino_usb.read_control(ControlSetup {
-        request_type: ControlSetupType::Standard,
-        request_recipient: ControlSetupRecipient::Device,
-        request: 0x06,
-        value: 0x0100,
-        index: 0,
-    });
-
-    println!("Device Descriptor: {:?}", response);
+    let device_descriptor = arduino_usb.parse_device_descriptor()?;
+    println!("Device Descriptor: {device_descriptor:?}");
+
+    let configuration_descriptor = arduino_usb.parse_configuration_descriptor(0)?;
+    println!("Configuration 0: {configuration_descriptor:?}");
 
     Ok(())
 }
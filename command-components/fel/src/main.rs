@@ -0,0 +1,84 @@
+mod protocol;
+
+use usb_wasm_bindings::device::UsbDevice;
+use usb_wasm_bindings::types::{Direction, Filter, TransferType};
+
+use anyhow::anyhow;
+
+use protocol::FelDevice;
+
+/// Allwinner's well-known FEL-mode USB vendor/product ID.
+const FEL_VENDOR_ID: u16 = 0x1f3a;
+const FEL_PRODUCT_ID: u16 = 0xefe8;
+
+fn get_fel_device() -> anyhow::Result<FelDevice> {
+    let device = UsbDevice::request_device(&Filter {
+        vendor_id: Some(FEL_VENDOR_ID),
+        product_id: Some(FEL_PRODUCT_ID),
+        ..Default::default()
+    })
+    .ok_or(anyhow!(
+        "No Allwinner FEL device found (is the board in recovery/FEL mode?)"
+    ))?;
+
+    let configuration = device.configurations().remove(0);
+    let interface = configuration
+        .interfaces()
+        .into_iter()
+        .find(|interface| {
+            interface.endpoints().into_iter().any(|ep| {
+                ep.descriptor().direction == Direction::In
+                    && ep.descriptor().transfer_type == TransferType::Bulk
+            })
+        })
+        .ok_or(anyhow!("Could not find FEL interface"))?;
+
+    Ok(FelDevice::new(device, configuration, interface))
+}
+
+pub fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let command = args
+        .next()
+        .ok_or(anyhow!("Usage: fel <verify|read|write|exec> [args...]"))?;
+
+    let mut fel = get_fel_device()?;
+
+    match command.as_str() {
+        "verify" => {
+            let info = fel.verify_device()?;
+            println!(
+                "{} SoC ID: {:#010x}, protocol: {:#06x}, scratchpad: {:#010x}",
+                info.signature.trim(),
+                info.soc_id,
+                info.protocol_version,
+                info.scratchpad
+            );
+        }
+        "read" => {
+            let address = parse_u32(&args.next().ok_or(anyhow!("Usage: fel read <address> <length> <out-file>"))?)?;
+            let length = parse_u32(&args.next().ok_or(anyhow!("Usage: fel read <address> <length> <out-file>"))?)?;
+            let out_path = args.next().ok_or(anyhow!("Usage: fel read <address> <length> <out-file>"))?;
+            let data = fel.read_memory(address, length)?;
+            std::fs::write(out_path, data)?;
+        }
+        "write" => {
+            let address = parse_u32(&args.next().ok_or(anyhow!("Usage: fel write <address> <in-file>"))?)?;
+            let in_path = args.next().ok_or(anyhow!("Usage: fel write <address> <in-file>"))?;
+            let data = std::fs::read(in_path)?;
+            fel.write_memory(address, &data)?;
+        }
+        "exec" => {
+            let address = parse_u32(&args.next().ok_or(anyhow!("Usage: fel exec <address>"))?)?;
+            fel.execute(address)?;
+        }
+        other => return Err(anyhow!("Unknown command: {other}")),
+    }
+
+    Ok(())
+}
+
+fn parse_u32(value: &str) -> anyhow::Result<u32> {
+    let value = value.trim_start_matches("0x");
+    Ok(u32::from_str_radix(value, 16)?)
+}
@@ -0,0 +1,212 @@
+use bytes::{BufMut, BytesMut};
+use thiserror::Error;
+use usb_wasm_bindings::{
+    device::{UsbConfiguration, UsbDevice, UsbEndpoint, UsbInterface},
+    types::{Direction, TransferType},
+};
+
+#[derive(Debug, Error)]
+pub enum FelError {
+    #[error("AWUS status block was not meaningful (bad signature or mismatched tag)")]
+    InvalidStatus,
+    #[error("device reported a non-zero FEL status ({0:#010x})")]
+    CommandFailed(u32),
+}
+
+// FEL protocol message IDs (sunxi-tools' `aw_fel` command set).
+const FEL_VERIFY_DEVICE: u32 = 0x0001;
+const FEL_DOWNLOAD: u32 = 0x0101;
+const FEL_RUN: u32 = 0x0102;
+const FEL_UPLOAD: u32 = 0x0103;
+
+/// Whether an `AWUC` request wrapper's data stage moves host-to-device or
+/// device-to-host.
+enum AwDataDirection {
+    Write,
+    Read,
+}
+
+/// Identifying information reported by [`FelDevice::verify_device`]: the
+/// 8-byte ASCII signature every SoC echoes back (`"AWUSBFEX"`), its 32-bit
+/// SoC ID, the FEL protocol version it speaks, and a scratch RAM address
+/// the boot ROM makes available for staging small transfers.
+#[derive(Debug)]
+pub struct SocInfo {
+    pub signature: String,
+    pub soc_id: u32,
+    pub protocol_version: u16,
+    pub scratchpad: u32,
+}
+
+impl SocInfo {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            signature: String::from_utf8_lossy(&bytes[0..8]).into_owned(),
+            soc_id: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+            protocol_version: u16::from_le_bytes(bytes[12..14].try_into().unwrap()),
+            scratchpad: u32::from_le_bytes(bytes[16..20].try_into().unwrap()),
+        }
+    }
+}
+
+/// Talks to an Allwinner SoC in FEL (USB boot ROM recovery) mode.
+///
+/// Framing is structurally the same shape as mass-storage's Bulk-Only
+/// Transport: a fixed-size `AWUC` request wrapper precedes each data stage
+/// (the 16-byte FEL command itself, or the read/write payload it
+/// describes), and a fixed-size `AWUS` status block follows it, so every
+/// exchange is one or two CBW/CSW-style round-trips depending on whether
+/// there's a data phase.
+pub struct FelDevice {
+    bulk_in: UsbEndpoint,
+    bulk_out: UsbEndpoint,
+    _interface: UsbInterface, // We need to keep these alive because of the endpoint resources
+    _configuration: UsbConfiguration, // We need to keep these alive because of the endpoint resources
+    device: UsbDevice,
+    current_tag: u32,
+}
+
+impl FelDevice {
+    // Also opens the device, selects the configuration, and claims the interface
+    pub fn new(device: UsbDevice, configuration: UsbConfiguration, interface: UsbInterface) -> Self {
+        device.open();
+        if device.active_configuration().descriptor().number != configuration.descriptor().number {
+            device.select_configuration(&configuration);
+        }
+        device.claim_interface(&interface);
+
+        let (bulk_in, bulk_out) = (
+            interface
+                .endpoints()
+                .into_iter()
+                .find(|ep| {
+                    ep.descriptor().direction == Direction::In
+                        && ep.descriptor().transfer_type == TransferType::Bulk
+                })
+                .expect("FEL interface always exposes a bulk IN endpoint"),
+            interface
+                .endpoints()
+                .into_iter()
+                .find(|ep| {
+                    ep.descriptor().direction == Direction::Out
+                        && ep.descriptor().transfer_type == TransferType::Bulk
+                })
+                .expect("FEL interface always exposes a bulk OUT endpoint"),
+        );
+
+        FelDevice {
+            device,
+            _configuration: configuration,
+            _interface: interface,
+
+            bulk_in,
+            bulk_out,
+
+            current_tag: 0,
+        }
+    }
+
+    fn get_tag(&mut self) -> u32 {
+        let tag = self.current_tag;
+        self.current_tag += 1;
+        tag
+    }
+
+    /// Sends the 32-byte `AWUC` request wrapper announcing a data stage of
+    /// `length` bytes moving in `direction`, the same bracketing role
+    /// `CommandBlockWrapper` plays for Bulk-Only Transport.
+    fn send_request_wrapper(&mut self, tag: u32, length: u32, direction: AwDataDirection) {
+        let mut wrapper = BytesMut::with_capacity(32);
+        wrapper.put_slice(b"AWUC");
+        wrapper.put_u32_le(tag);
+        wrapper.put_u32_le(length);
+        wrapper.put_u8(0x0c); // request type: USB bulk transfer request
+        wrapper.put_u8(match direction {
+            AwDataDirection::Write => 0x00,
+            AwDataDirection::Read => 0x01,
+        });
+        wrapper.put_bytes(0, 32 - wrapper.len());
+        self.device.write_bulk(&self.bulk_out, &wrapper);
+    }
+
+    /// Reads and validates the 13-byte `AWUS` status block that closes out
+    /// every `AWUC`-wrapped transfer, the same signature/tag check
+    /// mass-storage's Bulk-Only Transport does for its CSW.
+    fn read_status_wrapper(&mut self, tag: u32) -> Result<(), FelError> {
+        let status = self.device.read_bulk(&self.bulk_in, 13);
+        if status.len() != 13 || &status[0..4] != b"AWUS" {
+            return Err(FelError::InvalidStatus);
+        }
+
+        let status_tag = u32::from_le_bytes(status[4..8].try_into().unwrap());
+        if status_tag != tag {
+            return Err(FelError::InvalidStatus);
+        }
+
+        let fel_status = u32::from_le_bytes(status[8..12].try_into().unwrap());
+        if fel_status != 0 {
+            return Err(FelError::CommandFailed(fel_status));
+        }
+        Ok(())
+    }
+
+    /// Issues the 16-byte inner FEL request (`{command, address, length}`)
+    /// and waits for its status, without a data stage of its own -- the
+    /// data stage, if the command has one, is a separate `AWUC`/`AWUS`
+    /// round-trip issued by the caller afterwards.
+    fn send_command(&mut self, command: u32, address: u32, length: u32) -> Result<(), FelError> {
+        let tag = self.get_tag();
+
+        let mut request = BytesMut::with_capacity(16);
+        request.put_u32_le(command);
+        request.put_u32_le(address);
+        request.put_u32_le(length);
+        request.put_u32_le(0); // reserved
+
+        self.send_request_wrapper(tag, request.len() as u32, AwDataDirection::Write);
+        self.device.write_bulk(&self.bulk_out, &request);
+        self.read_status_wrapper(tag)
+    }
+
+    fn read_data(&mut self, length: u32) -> Result<Vec<u8>, FelError> {
+        let tag = self.get_tag();
+        self.send_request_wrapper(tag, length, AwDataDirection::Read);
+        let data = self.device.read_bulk(&self.bulk_in, length as u64);
+        self.read_status_wrapper(tag)?;
+        Ok(data)
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> Result<(), FelError> {
+        let tag = self.get_tag();
+        self.send_request_wrapper(tag, data.len() as u32, AwDataDirection::Write);
+        self.device.write_bulk(&self.bulk_out, data);
+        self.read_status_wrapper(tag)
+    }
+
+    /// Asks the boot ROM to identify itself: its SoC ID, the FEL protocol
+    /// version it speaks, and the scratch RAM address it reserves for
+    /// staging small transfers.
+    pub fn verify_device(&mut self) -> Result<SocInfo, FelError> {
+        self.send_command(FEL_VERIFY_DEVICE, 0, 0)?;
+        let data = self.read_data(32)?;
+        Ok(SocInfo::from_bytes(&data))
+    }
+
+    /// Reads `length` bytes of device memory starting at `address`.
+    pub fn read_memory(&mut self, address: u32, length: u32) -> Result<Vec<u8>, FelError> {
+        self.send_command(FEL_UPLOAD, address, length)?;
+        self.read_data(length)
+    }
+
+    /// Writes `data` into device memory starting at `address`.
+    pub fn write_memory(&mut self, address: u32, data: &[u8]) -> Result<(), FelError> {
+        self.send_command(FEL_DOWNLOAD, address, data.len() as u32)?;
+        self.write_data(data)
+    }
+
+    /// Jumps to and runs whatever code was previously staged at `address`
+    /// (typically via [`Self::write_memory`]), e.g. an SPL or U-Boot image.
+    pub fn execute(&mut self, address: u32) -> Result<(), FelError> {
+        self.send_command(FEL_RUN, address, 0)
+    }
+}
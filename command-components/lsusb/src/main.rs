@@ -1,3 +1,5 @@
+mod topology;
+
 use usb_wasm_bindings::{
     device::{UsbConfiguration, UsbDevice, UsbEndpoint, UsbInterface},
     types::{Direction, TransferType},
@@ -264,6 +266,11 @@ fn endpoint_section(endpoint: &UsbEndpoint) -> Section {
 }
 
 pub fn main() -> anyhow::Result<()> {
+    if std::env::args().any(|arg| arg == "-t") {
+        topology::print_tree(&UsbDevice::enumerate());
+        return Ok(());
+    }
+
     let mut first = true;
     for device in UsbDevice::enumerate() {
         if !first {
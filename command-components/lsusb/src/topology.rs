@@ -0,0 +1,168 @@
+use usb_wasm_bindings::device::UsbDevice;
+use usb_wasm_bindings::types::TransferType;
+
+/// The device descriptor only tells us the negotiated USB *version*, not the
+/// actual negotiated link speed, so Low vs Full speed devices (both bcdUSB
+/// 1.x) can't be told apart here — we report Full for 1.x, which is right
+/// for the common case and wrong only for genuine low-speed peripherals
+/// (mice, keyboards) masquerading at 1.x.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    Full,
+    High,
+    Super,
+}
+
+impl Speed {
+    fn for_device(device: &UsbDevice) -> Self {
+        match device.descriptor().usb_version.0 {
+            2 => Speed::High,
+            n if n >= 3 => Speed::Super,
+            _ => Speed::Full,
+        }
+    }
+
+    /// The label `lsusb -t` prints in its topology line.
+    fn label(self) -> &'static str {
+        match self {
+            Speed::Full => "12M",
+            Speed::High => "480M",
+            Speed::Super => "5000M",
+        }
+    }
+
+    /// Raw signaling bit rate, in bytes per microsecond, used to turn a
+    /// periodic endpoint's byte allocation into a time budget.
+    fn bytes_per_microsecond(self) -> f64 {
+        match self {
+            Speed::Full => 12_000_000.0 / 8.0 / 1_000_000.0,
+            Speed::High => 480_000_000.0 / 8.0 / 1_000_000.0,
+            Speed::Super => 5_000_000_000.0 / 8.0 / 1_000_000.0,
+        }
+    }
+
+    /// High/Super speed endpoints are polled in 125µs microframes; Full
+    /// speed uses a 1ms frame.
+    fn frame_budget_us(self) -> f64 {
+        match self {
+            Speed::Full => 1000.0,
+            Speed::High | Speed::Super => 125.0,
+        }
+    }
+
+    fn uses_microframes(self) -> bool {
+        matches!(self, Speed::High | Speed::Super)
+    }
+}
+
+/// Per the USB 2.0 spec, a high-speed endpoint's `wMaxPacketSize` packs the
+/// payload size in bits 10:0 and an "additional transactions per
+/// microframe" count in bits 12:11 (0 meaning just the one transaction).
+/// Full-speed endpoints don't use those high bits, so masking them out here
+/// is harmless either way.
+fn payload_bytes_per_interval(max_packet_size: u16) -> u32 {
+    let base = (max_packet_size & 0x7FF) as u32;
+    let additional_transactions = ((max_packet_size >> 11) & 0b11) as u32;
+    base * (additional_transactions + 1)
+}
+
+/// `bInterval` means different things depending on speed: 1-255 whole
+/// milliseconds for full speed, or 2^(bInterval-1) microframes (125µs each)
+/// for high/super speed.
+fn interval_frames(interval: u8, speed: Speed) -> f64 {
+    if speed.uses_microframes() {
+        2f64.powi(interval.saturating_sub(1) as i32)
+    } else {
+        interval as f64
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BandwidthSummary {
+    pub allocated_us: f64,
+    pub frame_budget_us: f64,
+    pub interrupt_count: usize,
+    pub isochronous_count: usize,
+}
+
+impl BandwidthSummary {
+    pub fn percent(&self) -> f64 {
+        if self.frame_budget_us == 0.0 {
+            0.0
+        } else {
+            100.0 * self.allocated_us / self.frame_budget_us
+        }
+    }
+}
+
+/// Sums the bus time every Interrupt/Isochronous endpoint on `device`
+/// reserves each (micro)frame, the same picture `lsusb -t`'s bandwidth line
+/// gives: whether the bus still has headroom or is already oversubscribed.
+pub fn compute_bandwidth(device: &UsbDevice) -> BandwidthSummary {
+    let speed = Speed::for_device(device);
+    let mut summary = BandwidthSummary {
+        frame_budget_us: speed.frame_budget_us(),
+        ..Default::default()
+    };
+
+    for interface in device.active_configuration().interfaces() {
+        for endpoint in interface.endpoints() {
+            let descriptor = endpoint.descriptor();
+            match descriptor.transfer_type {
+                TransferType::Interrupt => summary.interrupt_count += 1,
+                TransferType::Isochronous => summary.isochronous_count += 1,
+                _ => continue,
+            }
+
+            let bytes_per_interval = payload_bytes_per_interval(descriptor.max_packet_size);
+            let frames = interval_frames(descriptor.interval, speed).max(1.0);
+            let bytes_per_frame = bytes_per_interval as f64 / frames;
+            summary.allocated_us += bytes_per_frame / speed.bytes_per_microsecond();
+        }
+    }
+
+    summary
+}
+
+/// Prints a `lsusb -t`-style topology + bandwidth tree.
+///
+/// The guest bindings only expose a flat `UsbDevice::enumerate()` with no
+/// hub/port/parent linkage, so unlike real `lsusb -t` we can't nest hubs
+/// under their parent — every device is printed as a direct child of a
+/// single synthetic root, numbered by its position in the enumeration
+/// order rather than its real bus/port address.
+pub fn print_tree(devices: &[UsbDevice]) {
+    println!("/:  Bus 01.Port 001: Dev 001, Class=root_hub, Driver=usb-wasm/{}p", devices.len());
+
+    for (index, device) in devices.iter().enumerate() {
+        let descriptor = device.descriptor();
+        let speed = Speed::for_device(device);
+        let interface_count: usize = device
+            .active_configuration()
+            .interfaces()
+            .into_iter()
+            .count();
+
+        println!(
+            "    |__ Port {:03}: Dev {:03}, If {}, ID={:#06x}:{:#06x}, Driver=usb-wasm, {}",
+            index + 1,
+            index + 1,
+            interface_count,
+            descriptor.vendor_id,
+            descriptor.product_id,
+            speed.label(),
+        );
+
+        let bandwidth = compute_bandwidth(device);
+        if bandwidth.interrupt_count > 0 || bandwidth.isochronous_count > 0 {
+            println!(
+                "        Bandwidth: {:.1}us / {:.0}us ({:.1}%), #Int={}, #Iso={}",
+                bandwidth.allocated_us,
+                bandwidth.frame_budget_us,
+                bandwidth.percent(),
+                bandwidth.interrupt_count,
+                bandwidth.isochronous_count,
+            );
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::VecDeque;
+use std::fmt::Debug;
+
+use ahash::AHashMap;
+
+use crate::lru::HashKey;
+
+/// Adaptive Replacement Cache (Megiddo & Modha, 2003).
+///
+/// Recency and frequency are tracked as two separate lists over the live
+/// cache (`t1`: seen once recently, `t2`: seen at least twice), each backed
+/// by a same-sized ghost list of evicted keys (`b1`, `b2`) that remembers
+/// *who* was evicted without paying to keep their values around. A hit in
+/// either ghost list nudges the target T1 size `p` toward whichever
+/// discipline (recency or frequency) would have kept that key cached,
+/// adapting to the workload instead of committing to one policy.
+///
+/// `values` holds the data for exactly the keys currently in `t1` or `t2`;
+/// `b1`/`b2` only ever hold keys, never values.
+pub struct ArcCache<K: HashKey, V: Debug> {
+    capacity: usize,
+    p: usize,
+    t1: VecDeque<K>, // MRU at front, LRU at back
+    t2: VecDeque<K>,
+    b1: VecDeque<K>,
+    b2: VecDeque<K>,
+    values: AHashMap<K, V>,
+}
+
+impl<K: HashKey, V: Debug> ArcCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            p: 0,
+            t1: VecDeque::new(),
+            t2: VecDeque::new(),
+            b1: VecDeque::new(),
+            b2: VecDeque::new(),
+            values: AHashMap::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn list_contains(list: &VecDeque<K>, key: &K) -> bool {
+        list.iter().any(|k| k == key)
+    }
+
+    fn list_remove(list: &mut VecDeque<K>, key: &K) -> bool {
+        match list.iter().position(|k| k == key) {
+            Some(pos) => {
+                list.remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A hit in either `t1` or `t2` promotes the key to the MRU of `t2`,
+    /// since being accessed twice is what `t2` (the frequency list) means.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        if Self::list_remove(&mut self.t1, key) || Self::list_remove(&mut self.t2, key) {
+            self.t2.push_front(key.clone());
+        }
+        self.values.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        if Self::list_remove(&mut self.t1, key) || Self::list_remove(&mut self.t2, key) {
+            self.t2.push_front(key.clone());
+        }
+        self.values.get_mut(key)
+    }
+
+    /// Evicts the REPLACE victim per the ARC rule: from `t1` into `b1` once
+    /// `t1` has grown past its target `p` (or sits exactly at `p` while the
+    /// fault came from `b2`), otherwise from `t2` into `b2`.
+    fn replace(&mut self, faulting_key: &K) -> Option<(K, V)> {
+        let evict_from_t1 = !self.t1.is_empty()
+            && (self.t1.len() > self.p
+                || (self.t1.len() == self.p && Self::list_contains(&self.b2, faulting_key)));
+
+        if evict_from_t1 {
+            let evicted_key = self.t1.pop_back()?;
+            let value = self.values.remove(&evicted_key)?;
+            self.b1.push_front(evicted_key.clone());
+            Some((evicted_key, value))
+        } else {
+            let evicted_key = self.t2.pop_back()?;
+            let value = self.values.remove(&evicted_key)?;
+            self.b2.push_front(evicted_key.clone());
+            Some((evicted_key, value))
+        }
+    }
+
+    // If a value had to be evicted, returns the evicted key and value
+    pub fn set(&mut self, key: K, value: V) -> Option<(K, V)> {
+        // Already live: this is a hit that happens to be re-inserting, not
+        // a fault, so just refresh it in place (promoting to t2, as get()
+        // would) without touching p or the ghost lists.
+        if Self::list_remove(&mut self.t1, &key) || Self::list_remove(&mut self.t2, &key) {
+            self.t2.push_front(key.clone());
+            self.values.insert(key, value);
+            return None;
+        }
+
+        let c = self.capacity;
+
+        // Case I: x was recently evicted from t1 -- recency is winning,
+        // grow p (t1's target size) to favor it more.
+        if Self::list_contains(&self.b1, &key) {
+            let delta = (self.b2.len() / self.b1.len()).max(1);
+            self.p = (self.p + delta).min(c);
+            let evicted = self.replace(&key);
+            Self::list_remove(&mut self.b1, &key);
+            self.t2.push_front(key.clone());
+            self.values.insert(key, value);
+            return evicted;
+        }
+
+        // Case II: x was recently evicted from t2 -- frequency is winning,
+        // shrink p to favor it more.
+        if Self::list_contains(&self.b2, &key) {
+            let delta = (self.b1.len() / self.b2.len()).max(1);
+            self.p = self.p.saturating_sub(delta);
+            let evicted = self.replace(&key);
+            Self::list_remove(&mut self.b2, &key);
+            self.t2.push_front(key.clone());
+            self.values.insert(key, value);
+            return evicted;
+        }
+
+        // Case III/IV: x is new to the cache (not even a ghost).
+        let mut evicted = None;
+        if self.t1.len() + self.b1.len() == c {
+            if self.t1.len() < c {
+                self.b1.pop_back();
+                evicted = self.replace(&key);
+            } else if let Some(evicted_key) = self.t1.pop_back() {
+                // B1 is empty here, so there's no ghost list room to grow
+                // into: drop the LRU of t1 straight out of the cache.
+                if let Some(v) = self.values.remove(&evicted_key) {
+                    evicted = Some((evicted_key, v));
+                }
+            }
+        } else if self.t1.len() + self.t2.len() + self.b1.len() + self.b2.len() >= c {
+            if self.t1.len() + self.t2.len() + self.b1.len() + self.b2.len() >= 2 * c {
+                self.b2.pop_back();
+            }
+            evicted = self.replace(&key);
+        }
+
+        self.t1.push_front(key.clone());
+        self.values.insert(key, value);
+        evicted
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        Self::list_remove(&mut self.t1, key);
+        Self::list_remove(&mut self.t2, key);
+        // A removed key shouldn't linger as a ghost either, or a later
+        // fault on it would be wrongly credited as a ghost hit.
+        Self::list_remove(&mut self.b1, key);
+        Self::list_remove(&mut self.b2, key);
+        self.values.remove(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.values.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        self.values.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_to_capacity_then_evicts_the_lru_of_t1() {
+        let mut cache = ArcCache::new(2);
+        assert!(cache.set(1, "a").is_none());
+        assert!(cache.set(2, "b").is_none());
+
+        // t1 is full and b1 is still empty, so the next fault drops the LRU
+        // of t1 (key 1) straight out rather than ghosting it.
+        let evicted = cache.set(3, "c");
+        assert_eq!(evicted, Some((1, "a")));
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&"b"));
+        assert_eq!(cache.get(&3), Some(&"c"));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn a_second_hit_promotes_an_entry_from_t1_to_t2() {
+        let mut cache = ArcCache::new(2);
+        cache.set(1, "a");
+        cache.set(2, "b");
+
+        // First hit on 1 promotes it to t2, which makes 2 -- not 1 -- the
+        // next eviction victim even though 1 was inserted first.
+        assert_eq!(cache.get(&1), Some(&"a"));
+        let evicted = cache.set(3, "c");
+        assert_eq!(evicted, Some((2, "b")));
+        assert_eq!(cache.get(&1), Some(&"a"));
+    }
+
+    /// Walks the ARC replacement rule through both ghost-list transitions:
+    /// a hit in `b1` (Case I) grows `p` toward favoring recency, and a
+    /// later hit in `b2` (Case II) shrinks it back toward favoring
+    /// frequency -- and each ghost hit correctly reinstates its key with
+    /// the new value instead of leaving it evicted.
+    #[test]
+    fn ghost_list_hits_adjust_p_and_reinstate_the_evicted_key() {
+        let mut cache = ArcCache::new(2);
+        cache.set(1, "a"); // t1=[1]
+        cache.set(2, "b"); // t1=[2, 1]
+        assert_eq!(cache.get(&1), Some(&"a")); // t1=[2], t2=[1]: 1 is now "hot"
+
+        // New key with t1 under capacity (1) and the four lists at
+        // capacity overall (t1+t2 == 2): this faults through `replace`,
+        // which (t1 non-empty, p==0) takes from t1 and ghosts the evicted
+        // key into b1 instead of dropping it. t1=[3], t2=[1], b1=[2].
+        let evicted = cache.set(3, "c");
+        assert_eq!(evicted, Some((2, "b")));
+        assert_eq!(cache.get(&2), None); // gone from the live cache...
+        assert_eq!(cache.get(&3), Some(&"c")); // ...which also promotes 3 to t2: t1=[], t2=[3, 1]
+
+        // 2 is now only a b1 ghost. Re-inserting it is a ghost hit (Case
+        // I): p grows from 0 to 1. t1 is empty, so `replace` still takes
+        // from t2 regardless -- it evicts 1, t2's LRU entry -- but 2 comes
+        // back live in t2 with its new value and b1's ghost entry is
+        // cleared.
+        let evicted = cache.set(2, "b2");
+        assert_eq!(evicted, Some((1, "a")));
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&"b2"));
+        assert_eq!(cache.get(&3), Some(&"c"));
+        assert_eq!(cache.len(), 2);
+
+        // 1 is now a b2 ghost (evicted by the Case I hit above). Re-
+        // inserting it is a Case II hit: p shrinks back down to 0,
+        // reversing the Case I adjustment. `replace` again takes from t2
+        // (t1 is still empty), evicting 2 this time.
+        let evicted = cache.set(1, "a2");
+        assert_eq!(evicted, Some((2, "b2")));
+        assert_eq!(cache.get(&1), Some(&"a2"));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3), Some(&"c"));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn remove_scrubs_both_the_live_entry_and_any_ghost_bookkeeping() {
+        let mut cache = ArcCache::new(2);
+        cache.set(1, "a");
+        cache.set(2, "b");
+        cache.set(3, "c"); // evicts 1 straight out (b1 empty, see above)
+
+        assert_eq!(cache.remove(&2), Some("b"));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(&2), None);
+
+        // 1 was never ghosted (dropped straight out when evicted above),
+        // so re-inserting it is an ordinary fault, not a ghost hit: it
+        // shouldn't evict anything since the cache has room again.
+        assert!(cache.set(1, "a2").is_none());
+        assert_eq!(cache.get(&1), Some(&"a2"));
+    }
+}
@@ -10,8 +10,10 @@ use usb_wasm_bindings::{
 pub enum BulkOnlyTransportError {
     #[error("Invalid LUN")]
     InvalidLUN,
-    #[error("The device responded with a differnt tag than was expected")]
-    IncorrectTag,
+    #[error("Command phase error; performed Reset Recovery to resynchronize the transport")]
+    PhaseError,
+    #[error("Command Status Wrapper was not meaningful even after clearing the halt and retrying the status read; performed Reset Recovery to resynchronize the transport")]
+    ResetRecovered,
 }
 
 // Implementation of the base Bulk Only Transfer protocol
@@ -101,7 +103,7 @@ impl BulkOnlyTransportDevice {
     }
 
     pub fn select_lun(&mut self, lun: u8) -> Result<(), BulkOnlyTransportError> {
-        if self.max_lun > lun {
+        if lun > self.max_lun {
             return Err(BulkOnlyTransportError::InvalidLUN);
         }
         self.selected_lun = lun;
@@ -132,22 +134,26 @@ impl BulkOnlyTransportDevice {
         let cbw_bytes = cbw.to_bytes();
         self.device.write_bulk(&self.bulk_out, &cbw_bytes);
 
-        // TODO: implement proper error recovery
-        // First, implement errrors in the WIT interface though
-        // then, see section 5.3.3 and Figure 2 of the USB Mass Storage Class – Bulk Only Transport document
-
         // TODO: data stage
-        let transfer_length = cbw.transfer_length as usize;
+        let transfer_length = cbw.transfer_length;
         // Receive data
-        let data = self.device.read_bulk(&self.bulk_in, transfer_length as u64);
+        let mut data = self.device.read_bulk(&self.bulk_in, transfer_length as u64);
 
-        let csw_bytes = self.device.read_bulk(&self.bulk_in, 13);
-        let csw = CommandStatusWrapper::from_bytes(csw_bytes);
+        let csw = self.read_csw(tag, transfer_length)?;
 
-        if csw.tag != tag {
-            return Err(BulkOnlyTransportError::IncorrectTag);
+        // Per section 5.3.3 of the Bulk-Only Transport spec, a phase error
+        // means the device's state machine desynchronized from ours; Reset
+        // Recovery is the only way back to a known-good state.
+        if csw.status == CommandStatusWrapperStatus::PhaseError {
+            self.reset_recovery();
+            return Err(BulkOnlyTransportError::PhaseError);
         }
 
+        // data_residue is how many of the requested bytes the device didn't
+        // actually deliver (e.g. it stalled mid-stage), so trim to what it
+        // really sent instead of handing back the zero-padded remainder.
+        data.truncate(data.len().saturating_sub(csw.data_residue as usize));
+
         trace!("Received Command Status: {:?}", csw);
         Ok((csw, data))
     }
@@ -177,32 +183,122 @@ impl BulkOnlyTransportDevice {
         trace!("CBW Bytes: {:?}", cbw_bytes);
         self.device.write_bulk(&self.bulk_out, &cbw_bytes);
 
-        // TODO: implement proper error recovery
-        // First, implement errrors in the WIT interface though
-        // then, see section 5.3.3 and Figure 2 of the USB Mass Storage Class – Bulk Only Transport document
-
         if let Some(data) = data {
             self.device.write_bulk(&self.bulk_out, data);
         }
 
-        let csw_bytes = self.device.read_bulk(&self.bulk_in, 13);
-        let csw = CommandStatusWrapper::from_bytes(csw_bytes);
+        let csw = self.read_csw(tag, command_block.transfer_length)?;
 
-        if csw.tag != tag {
-            return Err(BulkOnlyTransportError::IncorrectTag);
+        if csw.status == CommandStatusWrapperStatus::PhaseError {
+            self.reset_recovery();
+            return Err(BulkOnlyTransportError::PhaseError);
         }
 
         trace!("Received Command Status: {:?}", csw);
         Ok(csw)
     }
 
+    /// Drives a single SCSI command through the transport without the
+    /// caller having to build a [`BulkOnlyTransportCommandBlock`] or pick
+    /// between [`Self::command_in`]/[`Self::command_out`] itself. For
+    /// `Direction::In`, `data`'s length is the number of bytes to read
+    /// back (its contents are ignored); for `Direction::Out`, `data` is
+    /// what's sent in the data-out phase (an empty slice means no data
+    /// phase at all, e.g. for `TEST UNIT READY`).
+    pub fn command(
+        &mut self,
+        cdb: Vec<u8>,
+        direction: Direction,
+        data: &[u8],
+    ) -> Result<(Vec<u8>, CommandStatusWrapperStatus), BulkOnlyTransportError> {
+        let command_block = BulkOnlyTransportCommandBlock {
+            command_block: cdb,
+            transfer_length: data.len() as u32,
+        };
+        match direction {
+            Direction::In => {
+                let (csw, data) = self.command_in(command_block)?;
+                Ok((data, csw.status))
+            }
+            Direction::Out => {
+                let csw = self.command_out(command_block, (!data.is_empty()).then_some(data))?;
+                Ok((Vec::new(), csw.status))
+            }
+        }
+    }
+
     fn get_tag(&mut self) -> u32 {
         let tag = self.current_tag;
         self.current_tag += 1;
         tag
     }
+
+    /// Reads the 13-byte status phase and validates it's "meaningful" per
+    /// section 5.3.3 of the Bulk-Only Transport spec (right signature, our
+    /// tag, and a residue that doesn't exceed what we asked to transfer).
+    /// A STALL on the status phase surfaces here as a read that comes back
+    /// malformed rather than as a distinct error, so the recovery is the
+    /// same either way: clear the halt on bulk-in and read the status
+    /// phase once more before giving up on the transport entirely and
+    /// performing a full Reset Recovery.
+    fn read_csw(
+        &mut self,
+        tag: u32,
+        transfer_length: u32,
+    ) -> Result<CommandStatusWrapper, BulkOnlyTransportError> {
+        if let Some(csw) = self.try_read_csw(tag, transfer_length) {
+            return Ok(csw);
+        }
+
+        self.clear_halt(&self.bulk_in);
+        if let Some(csw) = self.try_read_csw(tag, transfer_length) {
+            return Ok(csw);
+        }
+
+        self.reset_recovery();
+        Err(BulkOnlyTransportError::ResetRecovered)
+    }
+
+    fn try_read_csw(&mut self, tag: u32, transfer_length: u32) -> Option<CommandStatusWrapper> {
+        let csw_bytes = self.device.read_bulk(&self.bulk_in, 13);
+        let csw = CommandStatusWrapper::from_bytes(csw_bytes)?;
+        (csw.tag == tag && csw.data_residue <= transfer_length).then_some(csw)
+    }
+
+    /// Bulk-Only Mass Storage Reset Recovery (section 5.3.3): a class
+    /// control-OUT reset followed by clearing the halt feature on both bulk
+    /// endpoints, so a stalled or phase-errored transport can be retried
+    /// cleanly instead of staying desynchronized.
+    fn reset_recovery(&mut self) {
+        self.device.write_control(
+            ControlSetup {
+                request_type: ControlSetupType::Class,
+                request_recipient: ControlSetupRecipient::Interface,
+                request: 0xFF,
+                value: 0,
+                index: self._interface.descriptor().interface_number as u16,
+            },
+            vec![],
+        );
+        self.clear_halt(&self.bulk_in);
+        self.clear_halt(&self.bulk_out);
+    }
+
+    fn clear_halt(&self, endpoint: &UsbEndpoint) {
+        self.device.write_control(
+            ControlSetup {
+                request_type: ControlSetupType::Standard,
+                request_recipient: ControlSetupRecipient::Endpoint,
+                request: 0x01, // CLEAR_FEATURE
+                value: 0x0000, // ENDPOINT_HALT
+                index: endpoint.descriptor().endpoint_address as u16,
+            },
+            vec![],
+        );
+    }
 }
 
+#[derive(Clone)]
 pub struct BulkOnlyTransportCommandBlock {
     pub command_block: Vec<u8>,
     pub transfer_length: u32,
@@ -261,12 +357,20 @@ pub enum CommandStatusWrapperStatus {
 }
 
 impl CommandStatusWrapper {
-    fn from_bytes(bytes: Vec<u8>) -> Self {
-        assert!(bytes.len() == 13, "CSW incorrect length");
+    /// Parses bytes into a CSW, or `None` if the length or signature is
+    /// wrong -- i.e. it isn't "meaningful" per section 5.3.3 of the
+    /// Bulk-Only Transport spec, independent of tag/residue (which the
+    /// caller checks once it knows what it asked for).
+    fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
+        if bytes.len() != 13 {
+            return None;
+        }
         let mut bytes = Bytes::from(bytes);
 
         let signature = bytes.get_u32_le();
-        assert!(signature == 0x53425355, "invalid CSW signature");
+        if signature != 0x53425355 {
+            return None;
+        }
 
         let tag = bytes.get_u32_le();
         let data_residue = bytes.get_u32_le();
@@ -278,10 +382,10 @@ impl CommandStatusWrapper {
             _ => CommandStatusWrapperStatus::Reserved,
         };
 
-        CommandStatusWrapper {
+        Some(CommandStatusWrapper {
             tag,
             data_residue,
             status,
-        }
+        })
     }
 }
@@ -0,0 +1,220 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::arc_cache::ArcCache;
+
+/// 4 KiB batches several of `fatfs`'s typical 512-byte sector accesses into
+/// one underlying read/write, while still being a small, FAT-cluster-sized
+/// unit to cache.
+pub const BLOCK_SIZE: usize = 4096;
+
+/// 256 blocks (1 MiB) of cached FAT metadata/data, enough to hold a
+/// directory's worth of entries and the active part of the FAT itself
+/// without much memory pressure.
+pub const CACHE_CAPACITY_BLOCKS: usize = 256;
+
+#[derive(Debug)]
+struct CachedBlock {
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+/// Wraps any `Read + Write + Seek` (typically a [`crate::fatfs_adapter::FatfsAdapter`]
+/// windowed to one partition) with an ARC (Adaptive Replacement Cache) of
+/// fixed-size blocks, so `fatfs`'s many small FAT/directory accesses
+/// coalesce into fewer, larger reads and writes against the underlying mass
+/// storage device instead of one bulk transport round-trip each. ARC's
+/// frequency list keeps hot metadata blocks (the FAT, the root directory)
+/// resident even while a large sequential file read or write streams a lot
+/// of cold data blocks through recency's side of the cache.
+///
+/// `Seek`/`Read`/`Write` positions are tracked purely in `cursor`, not by
+/// querying the inner stream's position, since loading or writing back a
+/// block moves the inner stream around independently of the cursor the
+/// caller sees.
+pub struct CachedBlockDevice<D> {
+    inner: D,
+    block_size: usize,
+    cache: ArcCache<u64, CachedBlock>,
+    cursor: u64,
+}
+
+impl<D: Read + Write + Seek> CachedBlockDevice<D> {
+    pub fn new(inner: D, block_size: usize, capacity_blocks: usize) -> Self {
+        Self {
+            inner,
+            block_size,
+            cache: ArcCache::new(capacity_blocks),
+            cursor: 0,
+        }
+    }
+
+    /// Ensures `block` is in the cache, reading it from `inner` (and
+    /// writing back whatever it evicts) if it isn't. A short read (fewer
+    /// than `block_size` bytes) means `inner` ended partway through the
+    /// block, which is recorded as-is so reads past it report EOF.
+    fn load_block(&mut self, block: u64) -> io::Result<()> {
+        if self.cache.get(&block).is_some() {
+            return Ok(());
+        }
+
+        self.inner
+            .seek(SeekFrom::Start(block * self.block_size as u64))?;
+        let mut data = vec![0u8; self.block_size];
+        let mut filled = 0;
+        while filled < data.len() {
+            let read = self.inner.read(&mut data[filled..])?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+        data.truncate(filled);
+
+        if let Some((evicted_block, evicted)) = self.cache.set(
+            block,
+            CachedBlock {
+                data,
+                dirty: false,
+            },
+        ) {
+            self.write_back(evicted_block, &evicted)?;
+        }
+        Ok(())
+    }
+
+    fn write_back(&mut self, block: u64, entry: &CachedBlock) -> io::Result<()> {
+        if !entry.dirty {
+            return Ok(());
+        }
+        self.inner
+            .seek(SeekFrom::Start(block * self.block_size as u64))?;
+        self.inner.write_all(&entry.data)
+    }
+
+    /// Flushes every dirty block, coalescing contiguous runs into a single
+    /// `write_all` so a multi-block flush costs one seek per run instead of
+    /// one per block.
+    fn flush_dirty(&mut self) -> io::Result<()> {
+        let mut dirty_blocks: Vec<u64> = self
+            .cache
+            .iter()
+            .filter(|(_, entry)| entry.dirty)
+            .map(|(block, _)| *block)
+            .collect();
+        dirty_blocks.sort_unstable();
+
+        let mut i = 0;
+        while i < dirty_blocks.len() {
+            let run_start = dirty_blocks[i];
+            let mut j = i + 1;
+            while j < dirty_blocks.len() && dirty_blocks[j] == dirty_blocks[j - 1] + 1 {
+                j += 1;
+            }
+
+            let mut run_data = Vec::with_capacity((j - i) * self.block_size);
+            for &block in &dirty_blocks[i..j] {
+                run_data.extend_from_slice(&self.cache.get(&block).unwrap().data);
+            }
+
+            self.inner
+                .seek(SeekFrom::Start(run_start * self.block_size as u64))?;
+            self.inner.write_all(&run_data)?;
+
+            for &block in &dirty_blocks[i..j] {
+                self.cache.get_mut(&block).unwrap().dirty = false;
+            }
+
+            i = j;
+        }
+
+        Ok(())
+    }
+}
+
+impl<D: Read + Write + Seek> Read for CachedBlockDevice<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            let block = self.cursor / self.block_size as u64;
+            let offset_in_block = (self.cursor % self.block_size as u64) as usize;
+
+            self.load_block(block)?;
+            let data = &self.cache.get(&block).unwrap().data;
+            if offset_in_block >= data.len() {
+                break; // inner ended inside (or before) this block
+            }
+
+            let n = (data.len() - offset_in_block).min(buf.len() - written);
+            buf[written..written + n].copy_from_slice(&data[offset_in_block..offset_in_block + n]);
+            written += n;
+            self.cursor += n as u64;
+
+            if data.len() < self.block_size {
+                break; // short block: nothing more to read
+            }
+        }
+        Ok(written)
+    }
+}
+
+impl<D: Read + Write + Seek> Write for CachedBlockDevice<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut consumed = 0;
+        while consumed < buf.len() {
+            let block = self.cursor / self.block_size as u64;
+            let offset_in_block = (self.cursor % self.block_size as u64) as usize;
+
+            self.load_block(block)?;
+            let entry = self.cache.get_mut(&block).unwrap();
+            if entry.data.len() < self.block_size {
+                entry.data.resize(self.block_size, 0);
+            }
+
+            let n = (self.block_size - offset_in_block).min(buf.len() - consumed);
+            entry.data[offset_in_block..offset_in_block + n]
+                .copy_from_slice(&buf[consumed..consumed + n]);
+            entry.dirty = true;
+
+            consumed += n;
+            self.cursor += n as u64;
+        }
+        Ok(consumed)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_dirty()?;
+        self.inner.flush()
+    }
+}
+
+impl<D: Read + Write + Seek> Seek for CachedBlockDevice<D> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.cursor = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(delta) => {
+                if delta >= 0 {
+                    self.cursor + delta as u64
+                } else {
+                    self.cursor - (-delta) as u64
+                }
+            }
+            SeekFrom::End(delta) => {
+                let end = self.inner.seek(SeekFrom::End(0))?;
+                if delta >= 0 {
+                    end + delta as u64
+                } else {
+                    end - (-delta) as u64
+                }
+            }
+        };
+        Ok(self.cursor)
+    }
+}
+
+impl<D: Read + Write + Seek> Drop for CachedBlockDevice<D> {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush_dirty() {
+            tracing::warn!(%err, "failed to flush cached block device on drop");
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use std::cell::RefCell;
+use std::io::{Read, Seek, SeekFrom};
+
+use chrono::{DateTime, Local};
+use thiserror::Error;
+
+/// Superblock magic ext2/ext3 puts at byte offset 56 within the superblock,
+/// which itself always starts 1024 bytes into the partition -- so probing
+/// the fixed absolute offset 1080 is enough to tell an ext2/ext3 partition
+/// apart from FAT without mounting anything.
+pub const EXT2_MAGIC: u16 = 0xEF53;
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const ROOT_INODE: u32 = 2;
+
+#[derive(Debug, Error)]
+pub enum Ext2Error {
+    #[error("not an ext2/ext3 filesystem (bad superblock magic)")]
+    NotExt2,
+    #[error("{0}: no such file or directory")]
+    NotFound(String),
+    #[error("{0}: not a directory")]
+    NotADirectory(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Reads the magic field at its fixed absolute offset without parsing the
+/// rest of the superblock, so callers can cheaply decide whether to hand
+/// the device off to [`Ext2Filesystem::open`] or to `fatfs` instead.
+/// Restores the stream position to the start before returning.
+pub fn probe<R: Read + Seek>(device: &mut R) -> std::io::Result<bool> {
+    let mut magic = [0u8; 2];
+    device.seek(SeekFrom::Start(SUPERBLOCK_OFFSET + 56))?;
+    device.read_exact(&mut magic)?;
+    device.seek(SeekFrom::Start(0))?;
+    Ok(u16::from_le_bytes(magic) == EXT2_MAGIC)
+}
+
+struct Superblock {
+    blocks_count: u32,
+    first_data_block: u32,
+    block_size: u32,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    inode_size: u16,
+}
+
+impl Superblock {
+    fn parse(bytes: &[u8]) -> Result<Self, Ext2Error> {
+        let magic = u16::from_le_bytes(bytes[56..58].try_into().unwrap());
+        if magic != EXT2_MAGIC {
+            return Err(Ext2Error::NotExt2);
+        }
+
+        let blocks_count = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        let first_data_block = u32::from_le_bytes(bytes[20..24].try_into().unwrap());
+        let log_block_size = u32::from_le_bytes(bytes[24..28].try_into().unwrap());
+        let blocks_per_group = u32::from_le_bytes(bytes[32..36].try_into().unwrap());
+        let inodes_per_group = u32::from_le_bytes(bytes[40..44].try_into().unwrap());
+        let rev_level = u32::from_le_bytes(bytes[76..80].try_into().unwrap());
+        // Revision 0 filesystems predate the dynamic inode size field and
+        // always use 128-byte inodes.
+        let inode_size = if rev_level == 0 {
+            128
+        } else {
+            u16::from_le_bytes(bytes[88..90].try_into().unwrap())
+        };
+
+        Ok(Superblock {
+            blocks_count,
+            first_data_block,
+            block_size: 1024 << log_block_size,
+            blocks_per_group,
+            inodes_per_group,
+            inode_size,
+        })
+    }
+
+    fn block_group_count(&self) -> u32 {
+        self.blocks_count.div_ceil(self.blocks_per_group)
+    }
+}
+
+struct BlockGroupDescriptor {
+    inode_table: u32,
+}
+
+impl BlockGroupDescriptor {
+    fn parse(bytes: &[u8]) -> Self {
+        BlockGroupDescriptor {
+            inode_table: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+        }
+    }
+}
+
+const INODE_MODE_TYPE_MASK: u16 = 0xF000;
+const INODE_MODE_DIR: u16 = 0x4000;
+const INODE_MODE_FILE: u16 = 0x8000;
+const DIRENT_FILE_TYPE_DIR: u8 = 2;
+
+struct Inode {
+    mode: u16,
+    size: u64,
+    mtime: u32,
+    block: [u32; 15],
+}
+
+impl Inode {
+    fn parse(bytes: &[u8]) -> Self {
+        let mode = u16::from_le_bytes(bytes[0..2].try_into().unwrap());
+        let size_lo = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        let mtime = u32::from_le_bytes(bytes[16..20].try_into().unwrap());
+        let size_hi = u32::from_le_bytes(bytes[108..112].try_into().unwrap());
+
+        let mut block = [0u32; 15];
+        for (i, slot) in block.iter_mut().enumerate() {
+            let offset = 40 + i * 4;
+            *slot = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        }
+
+        // Only regular files use the high 32 bits of size as a size
+        // extension; directories/symlinks leave that word as ACL data.
+        let size = if mode & INODE_MODE_TYPE_MASK == INODE_MODE_FILE {
+            ((size_hi as u64) << 32) | size_lo as u64
+        } else {
+            size_lo as u64
+        };
+
+        Inode {
+            mode,
+            size,
+            mtime,
+            block,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.mode & INODE_MODE_TYPE_MASK == INODE_MODE_DIR
+    }
+}
+
+/// One resolved directory entry, as returned by [`Ext2Filesystem::read_dir`].
+#[derive(Debug)]
+pub struct Ext2Entry {
+    pub name: String,
+    pub size: u64,
+    pub is_dir: bool,
+    pub modified: Option<DateTime<Local>>,
+}
+
+fn non_zero(block: u32) -> Option<u32> {
+    (block != 0).then_some(block)
+}
+
+/// A read-only ext2/ext3 reader layered directly over a raw partition
+/// stream (typically a [`crate::fatfs_adapter::FatfsAdapter`]), the same
+/// way `fatfs` is -- so `tree`/`ls`/`cat` can use whichever of the two
+/// matches what's actually on the partition. Paths resolve through direct,
+/// singly-, doubly-, and triply-indirect block pointers, so files larger
+/// than a single block (including ones spanning indirect blocks) read
+/// correctly.
+pub struct Ext2Filesystem<R> {
+    device: RefCell<R>,
+    superblock: Superblock,
+    block_groups: Vec<BlockGroupDescriptor>,
+}
+
+impl<R: Read + Seek> Ext2Filesystem<R> {
+    pub fn open(mut device: R) -> Result<Self, Ext2Error> {
+        let mut superblock_bytes = vec![0u8; 1024];
+        device.seek(SeekFrom::Start(SUPERBLOCK_OFFSET))?;
+        device.read_exact(&mut superblock_bytes)?;
+        let superblock = Superblock::parse(&superblock_bytes)?;
+
+        let bgdt_block = superblock.first_data_block + 1;
+        let mut bgdt_bytes = vec![0u8; superblock.block_group_count() as usize * 32];
+        device.seek(SeekFrom::Start(
+            bgdt_block as u64 * superblock.block_size as u64,
+        ))?;
+        device.read_exact(&mut bgdt_bytes)?;
+        let block_groups = bgdt_bytes
+            .chunks_exact(32)
+            .map(BlockGroupDescriptor::parse)
+            .collect();
+
+        Ok(Self {
+            device: RefCell::new(device),
+            superblock,
+            block_groups,
+        })
+    }
+
+    fn read_block(&self, block_num: u32) -> Result<Vec<u8>, Ext2Error> {
+        let mut buf = vec![0u8; self.superblock.block_size as usize];
+        let mut device = self.device.borrow_mut();
+        device.seek(SeekFrom::Start(
+            block_num as u64 * self.superblock.block_size as u64,
+        ))?;
+        device.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn read_inode(&self, inode_num: u32) -> Result<Inode, Ext2Error> {
+        let index = inode_num - 1;
+        let group = index / self.superblock.inodes_per_group;
+        let index_in_group = index % self.superblock.inodes_per_group;
+        let inode_table = self.block_groups[group as usize].inode_table;
+        let offset = inode_table as u64 * self.superblock.block_size as u64
+            + index_in_group as u64 * self.superblock.inode_size as u64;
+
+        // We only ever look at the first 128 bytes of an inode record, even
+        // when `inode_size` (ext3/4) is larger -- everything we read lives
+        // in that prefix.
+        let mut buf = vec![0u8; 128];
+        let mut device = self.device.borrow_mut();
+        device.seek(SeekFrom::Start(offset))?;
+        device.read_exact(&mut buf)?;
+        Ok(Inode::parse(&buf))
+    }
+
+    /// Reads the `u32` pointer at `index` out of the indirect block
+    /// `pointer_block` (itself a block full of pointers). Returns `None` if
+    /// either the indirect block or the entry inside it is unallocated.
+    fn indirect_pointer(&self, pointer_block: u32, index: u32) -> Result<Option<u32>, Ext2Error> {
+        let Some(pointer_block) = non_zero(pointer_block) else {
+            return Ok(None);
+        };
+        let block = self.read_block(pointer_block)?;
+        let offset = index as usize * 4;
+        Ok(non_zero(u32::from_le_bytes(
+            block[offset..offset + 4].try_into().unwrap(),
+        )))
+    }
+
+    /// Resolves logical block `index` of `inode`'s data through direct
+    /// (0-11), singly- (12), doubly- (13), and triply-indirect (14) block
+    /// pointers. Returns `None` for a hole -- a logical block that was
+    /// never allocated.
+    fn data_block(&self, inode: &Inode, index: u32) -> Result<Option<u32>, Ext2Error> {
+        let pointers_per_block = self.superblock.block_size / 4;
+
+        if index < 12 {
+            return Ok(non_zero(inode.block[index as usize]));
+        }
+        let index = index - 12;
+
+        if index < pointers_per_block {
+            return self.indirect_pointer(inode.block[12], index);
+        }
+        let index = index - pointers_per_block;
+
+        if index < pointers_per_block * pointers_per_block {
+            let outer = index / pointers_per_block;
+            let inner = index % pointers_per_block;
+            return match self.indirect_pointer(inode.block[13], outer)? {
+                Some(middle) => self.indirect_pointer(middle, inner),
+                None => Ok(None),
+            };
+        }
+        let index = index - pointers_per_block * pointers_per_block;
+
+        let outer = index / (pointers_per_block * pointers_per_block);
+        let remainder = index % (pointers_per_block * pointers_per_block);
+        let middle_index = remainder / pointers_per_block;
+        let inner = remainder % pointers_per_block;
+
+        let Some(middle_table) = self.indirect_pointer(inode.block[14], outer)? else {
+            return Ok(None);
+        };
+        match self.indirect_pointer(middle_table, middle_index)? {
+            Some(middle) => self.indirect_pointer(middle, inner),
+            None => Ok(None),
+        }
+    }
+
+    fn read_inode_data(&self, inode: &Inode) -> Result<Vec<u8>, Ext2Error> {
+        let block_size = self.superblock.block_size as u64;
+        let block_count = inode.size.div_ceil(block_size.max(1));
+        let mut data = Vec::with_capacity(inode.size as usize);
+
+        for logical_block in 0..block_count as u32 {
+            match self.data_block(inode, logical_block)? {
+                Some(physical_block) => data.extend(self.read_block(physical_block)?),
+                None => data.extend(std::iter::repeat_n(0u8, block_size as usize)),
+            }
+        }
+
+        data.truncate(inode.size as usize);
+        Ok(data)
+    }
+
+    fn list_dir_inode(&self, inode: &Inode) -> Result<Vec<(u32, Ext2Entry)>, Ext2Error> {
+        let data = self.read_inode_data(inode)?;
+        let mut entries = Vec::new();
+        let mut offset = 0;
+
+        while offset + 8 <= data.len() {
+            let entry_inode = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+            let rec_len = u16::from_le_bytes(data[offset + 4..offset + 6].try_into().unwrap());
+            let name_len = data[offset + 6] as usize;
+            let file_type = data[offset + 7];
+
+            if rec_len == 0 {
+                break; // corrupt directory block; stop rather than loop forever
+            }
+
+            if entry_inode != 0 {
+                let name_start = offset + 8;
+                let name = String::from_utf8_lossy(&data[name_start..name_start + name_len])
+                    .into_owned();
+                if name != "." && name != ".." {
+                    let child = self.read_inode(entry_inode)?;
+                    entries.push((
+                        entry_inode,
+                        Ext2Entry {
+                            name,
+                            size: child.size,
+                            is_dir: file_type == DIRENT_FILE_TYPE_DIR || child.is_dir(),
+                            modified: DateTime::from_timestamp(child.mtime as i64, 0)
+                                .map(|dt| dt.with_timezone(&Local)),
+                        },
+                    ));
+                }
+            }
+
+            offset += rec_len as usize;
+        }
+
+        Ok(entries)
+    }
+
+    /// Walks `path` (`/`-separated, relative to the root directory) one
+    /// component at a time, returning the inode it resolves to.
+    fn resolve(&self, path: &str) -> Result<Inode, Ext2Error> {
+        let mut inode = self.read_inode(ROOT_INODE)?;
+
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            if !inode.is_dir() {
+                return Err(Ext2Error::NotADirectory(component.to_owned()));
+            }
+            let (inode_num, _) = self
+                .list_dir_inode(&inode)?
+                .into_iter()
+                .find(|(_, entry)| entry.name == component)
+                .ok_or_else(|| Ext2Error::NotFound(component.to_owned()))?;
+            inode = self.read_inode(inode_num)?;
+        }
+
+        Ok(inode)
+    }
+
+    /// Lists the contents of the directory at `path`.
+    pub fn read_dir(&self, path: &str) -> Result<Vec<Ext2Entry>, Ext2Error> {
+        let inode = self.resolve(path)?;
+        if !inode.is_dir() {
+            return Err(Ext2Error::NotADirectory(path.to_owned()));
+        }
+        Ok(self
+            .list_dir_inode(&inode)?
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect())
+    }
+
+    /// Reads the whole contents of the file at `path`.
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, Ext2Error> {
+        let inode = self.resolve(path)?;
+        if inode.is_dir() {
+            return Err(Ext2Error::NotADirectory(path.to_owned()));
+        }
+        self.read_inode_data(&inode)
+    }
+}
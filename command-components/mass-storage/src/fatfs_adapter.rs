@@ -0,0 +1,89 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use fscommon::StreamSlice;
+
+use crate::mass_storage::MassStorageDevice;
+use crate::partition_table::{self, PartitionInfo};
+
+/// Bounds a [`MassStorageDevice`] to a single partition so it can be handed
+/// straight to `fatfs::FileSystem::new`, without every caller having to
+/// re-derive the MBR parsing and byte-range windowing `get_filesystem` used
+/// to do inline.
+///
+/// `FatfsAdapter` itself implements `std::io::{Read, Write, Seek}`, which is
+/// all `fatfs::ReadWriteSeek` requires (via fatfs's blanket `std` impl), so
+/// it can be passed directly where a `ReadWriteSeek` is expected.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use mass_storage::fatfs_adapter::FatfsAdapter;
+/// # use mass_storage::mass_storage::MassStorageDevice;
+/// # fn get_device() -> anyhow::Result<MassStorageDevice> { unimplemented!() }
+/// # fn main() -> anyhow::Result<()> {
+/// let device = get_device()?;
+/// let adapter = FatfsAdapter::open_first_partition(device)?;
+/// let fs = fatfs::FileSystem::new(adapter, fatfs::FsOptions::new())?;
+/// for entry in fs.root_dir().iter() {
+///     println!("{}", entry?.file_name());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct FatfsAdapter(StreamSlice<MassStorageDevice>);
+
+impl FatfsAdapter {
+    /// Reads the MBR off `device` and windows it to the first partition
+    /// table entry, so reads/writes through the adapter are relative to
+    /// that partition's start rather than the whole device.
+    pub fn open_first_partition(device: MassStorageDevice) -> anyhow::Result<Self> {
+        Self::open_partition(device, 0)
+    }
+
+    /// Lists every partition on `device` (MBR or GPT, see
+    /// [`partition_table::list_partitions`]), without consuming it.
+    pub fn list_partitions(mut device: MassStorageDevice) -> anyhow::Result<Vec<PartitionInfo>> {
+        let sector_size = device.get_properties().block_size;
+        partition_table::list_partitions(&mut device, sector_size)
+    }
+
+    /// Detects the device's partition table (GPT if the MBR is a
+    /// protective one, MBR otherwise) and windows the adapter to the
+    /// `index`-th partition in that table, so reads/writes through it are
+    /// relative to that partition's start rather than the whole device.
+    pub fn open_partition(mut device: MassStorageDevice, index: usize) -> anyhow::Result<Self> {
+        let sector_size = device.get_properties().block_size;
+        let partitions = partition_table::list_partitions(&mut device, sector_size)?;
+        let partition = partitions
+            .into_iter()
+            .find(|partition| partition.index == index)
+            .ok_or_else(|| anyhow::anyhow!("No partition {index} found"))?;
+
+        let (start, end) = partition.byte_range();
+        let stream = StreamSlice::new(device, start, end)
+            .map_err(|err| anyhow::anyhow!("failed to window device to partition: {err}"))?;
+        Ok(Self(stream))
+    }
+}
+
+impl Read for FatfsAdapter {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for FatfsAdapter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Seek for FatfsAdapter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
@@ -0,0 +1,174 @@
+use usb_wasm_bindings::{
+    device::{UsbConfiguration, UsbDevice, UsbEndpoint, UsbInterface},
+    types::{ControlSetup, ControlSetupRecipient, ControlSetupType, Direction, TransferType},
+};
+
+const REQUEST_RESET: u8 = 0x00;
+const REQUEST_SET_FLOW_CTRL: u8 = 0x02;
+const REQUEST_SET_BAUD_RATE: u8 = 0x03;
+const REQUEST_SET_DATA: u8 = 0x04;
+
+const SIO_RESET_SIO: u16 = 0;
+const SIO_DISABLE_FLOW_CTRL: u16 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    Mark,
+    Space,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    OnePointFive,
+    Two,
+}
+
+// FTDI's fractional baud divisor is encoded as one of these eighths,
+// looked up by the chip's own bit pattern rather than the raw binary
+// fraction.
+const FRACTIONAL_DIVISOR_CODES: [u16; 8] = [0, 3, 2, 4, 1, 5, 6, 7];
+
+/// Encodes `baud` into the (value, index) pair `SET_BAUD_RATE` expects: a
+/// 14-bit integer divisor of the chip's 3 MHz reference clock (48 MHz / 16)
+/// plus a 3-bit fractional part, with the top fractional bit spilling over
+/// into `index` the way it does on real FTDI silicon.
+fn encode_baud_rate(baud: u32) -> (u16, u16) {
+    const BASE_CLOCK_X8: u32 = 3_000_000 * 8;
+    let divisor_x8 = BASE_CLOCK_X8 / baud;
+    let integer_part = (divisor_x8 / 8) as u16 & 0x3FFF;
+    let fractional_code = FRACTIONAL_DIVISOR_CODES[(divisor_x8 % 8) as usize];
+
+    let value = integer_part | ((fractional_code & 0b011) << 14);
+    let index = (fractional_code & 0b100) >> 2;
+    (value, index)
+}
+
+fn encode_line_properties(data_bits: u8, parity: Parity, stop_bits: StopBits) -> u16 {
+    let parity_bits: u16 = match parity {
+        Parity::None => 0,
+        Parity::Odd => 1,
+        Parity::Even => 2,
+        Parity::Mark => 3,
+        Parity::Space => 4,
+    };
+    let stop_bits_value: u16 = match stop_bits {
+        StopBits::One => 0,
+        StopBits::OnePointFive => 1,
+        StopBits::Two => 2,
+    };
+
+    data_bits as u16 | (parity_bits << 8) | (stop_bits_value << 11)
+}
+
+// Turns an FTDI-based USB-serial adapter into a byte stream, the same way
+// BulkOnlyTransportDevice turns a mass-storage interface into SCSI commands
+// over bulk endpoints: the caller hands us an already-opened device and
+// interface (selected by VID/PID, since FTDI chips don't advertise a
+// standard interface class), and we speak the vendor control protocol and
+// de-chunk the modem-status-prefixed Bulk-IN packets.
+pub struct FtdiDevice {
+    bulk_in: UsbEndpoint,
+    bulk_out: UsbEndpoint,
+    _interface: UsbInterface, // We need to keep these alive because of the endpoint resources
+    _configuration: UsbConfiguration, // We need to keep these alive because of the endpoint resources
+    device: UsbDevice,
+}
+
+impl FtdiDevice {
+    pub fn new(device: UsbDevice, configuration: UsbConfiguration, interface: UsbInterface) -> Self {
+        device.open();
+        device.reset();
+        if device.active_configuration().descriptor().number != configuration.descriptor().number {
+            device.select_configuration(&configuration);
+        };
+        device.claim_interface(&interface);
+
+        let (bulk_in, bulk_out) = (
+            interface
+                .endpoints()
+                .into_iter()
+                .find(|ep| {
+                    ep.descriptor().direction == Direction::In
+                        && ep.descriptor().transfer_type == TransferType::Bulk
+                })
+                .unwrap(),
+            interface
+                .endpoints()
+                .into_iter()
+                .find(|ep| {
+                    ep.descriptor().direction == Direction::Out
+                        && ep.descriptor().transfer_type == TransferType::Bulk
+                })
+                .unwrap(),
+        );
+
+        FtdiDevice {
+            device,
+            _configuration: configuration,
+            _interface: interface,
+            bulk_in,
+            bulk_out,
+        }
+    }
+
+    /// Resets the chip and configures baud rate, line properties, and flow
+    /// control (always disabled), in the order the FTDI application note
+    /// recommends: reset first, then the line settings that persist across
+    /// it.
+    pub fn open(&self, baud: u32, data_bits: u8, parity: Parity, stop_bits: StopBits) {
+        self.vendor_control(REQUEST_RESET, SIO_RESET_SIO, 0);
+        self.vendor_control(
+            REQUEST_SET_DATA,
+            encode_line_properties(data_bits, parity, stop_bits),
+            0,
+        );
+        self.vendor_control(REQUEST_SET_FLOW_CTRL, 0, SIO_DISABLE_FLOW_CTRL);
+
+        let (value, index) = encode_baud_rate(baud);
+        self.vendor_control(REQUEST_SET_BAUD_RATE, value, index);
+    }
+
+    pub fn write(&self, data: &[u8]) {
+        self.device.write_bulk(&self.bulk_out, data);
+    }
+
+    /// Reads up to `max` bytes of payload, stripping the 2-byte modem-status
+    /// prefix every FTDI Bulk-IN packet carries ahead of its data (so a
+    /// multi-packet transfer has to be de-chunked packet-by-packet rather
+    /// than just dropping the first 2 bytes of the whole buffer).
+    pub fn read(&self, max: usize) -> Vec<u8> {
+        let max_packet_size = self.bulk_in.descriptor().max_packet_size as usize;
+        let payload_per_packet = max_packet_size.saturating_sub(2).max(1);
+        let packets = max.div_ceil(payload_per_packet);
+
+        let raw = self
+            .device
+            .read_bulk(&self.bulk_in, (packets * max_packet_size) as u64);
+
+        let mut data = Vec::with_capacity(max);
+        for packet in raw.chunks(max_packet_size) {
+            if packet.len() > 2 {
+                data.extend_from_slice(&packet[2..]);
+            }
+        }
+        data.truncate(max);
+        data
+    }
+
+    fn vendor_control(&self, request: u8, value: u16, index: u16) {
+        self.device.write_control(
+            ControlSetup {
+                request_type: ControlSetupType::Vendor,
+                request_recipient: ControlSetupRecipient::Device,
+                request,
+                value,
+                index,
+            },
+            vec![],
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::{Read, Write};
+
+use bytes::{BufMut, BytesMut};
+
+use crate::mass_storage::MassStorageDevice;
+
+/// Number of logical blocks grouped into a single compressible unit when
+/// dumping/restoring an image. Large enough that per-group zstd overhead is
+/// negligible, small enough that an all-zero run only a few groups long
+/// already wins over storing it raw.
+const GROUP_BLOCKS: u32 = 2048;
+
+const IMAGE_MAGIC: [u8; 4] = *b"UWIM";
+const IMAGE_FORMAT_VERSION: u8 = 1;
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GroupKind {
+    AllZero,
+    Raw,
+    Zstd,
+}
+
+impl GroupKind {
+    fn tag(self) -> u8 {
+        match self {
+            GroupKind::AllZero => 0,
+            GroupKind::Raw => 1,
+            GroupKind::Zstd => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> anyhow::Result<Self> {
+        Ok(match tag {
+            0 => GroupKind::AllZero,
+            1 => GroupKind::Raw,
+            2 => GroupKind::Zstd,
+            other => anyhow::bail!("unknown image group kind tag {other}"),
+        })
+    }
+}
+
+/// Streams the whole device to `out` as a sparse, per-group compressed
+/// image: a header (magic, format version, block size, total block count,
+/// group size), a block-map table with one entry per group of
+/// [`GROUP_BLOCKS`] logical blocks marking it all-zero / raw / zstd, then
+/// the stored group payloads back to back in group order. All-zero groups
+/// cost just their map entry, so unallocated flash doesn't bloat the image.
+pub fn dump_image<W: Write>(device: &mut MassStorageDevice, mut out: W) -> anyhow::Result<()> {
+    let properties = device.get_properties();
+    let block_size = properties.block_size;
+    let total_blocks = properties.total_number_of_blocks;
+    let group_count = total_blocks.div_ceil(GROUP_BLOCKS as u64);
+
+    let mut header = BytesMut::new();
+    header.put_slice(&IMAGE_MAGIC);
+    header.put_u8(IMAGE_FORMAT_VERSION);
+    header.put_u32(block_size);
+    header.put_u64(total_blocks);
+    header.put_u32(GROUP_BLOCKS);
+    out.write_all(&header)?;
+
+    let mut tags = Vec::with_capacity(group_count as usize);
+    let mut payloads = Vec::new();
+
+    for group in 0..group_count {
+        let start_block = group * GROUP_BLOCKS as u64;
+        let blocks_in_group = GROUP_BLOCKS.min((total_blocks - start_block) as u32);
+        let data = device.read_blocks(start_block, blocks_in_group)?;
+
+        if data.iter().all(|&byte| byte == 0) {
+            tags.push((GroupKind::AllZero, 0u32));
+            continue;
+        }
+
+        let compressed = zstd::bulk::compress(&data, ZSTD_LEVEL)?;
+        if compressed.len() < data.len() {
+            tags.push((GroupKind::Zstd, compressed.len() as u32));
+            payloads.push(compressed);
+        } else {
+            tags.push((GroupKind::Raw, data.len() as u32));
+            payloads.push(data);
+        }
+    }
+
+    for (kind, payload_len) in &tags {
+        out.write_all(&[kind.tag()])?;
+        out.write_all(&payload_len.to_be_bytes())?;
+    }
+    for payload in payloads {
+        out.write_all(&payload)?;
+    }
+
+    Ok(())
+}
+
+/// Reverses [`dump_image`]: replays the block-map table, skipping WRITE(10)
+/// entirely for all-zero groups (optionally UNMAP-ing them when
+/// `discard_zero_groups` is set and the device is thin provisioned) and
+/// decompressing zstd groups before writing them back. Cost is proportional
+/// to the data the image actually stored rather than to the whole device.
+pub fn restore_image<R: Read>(
+    device: &mut MassStorageDevice,
+    mut inp: R,
+    discard_zero_groups: bool,
+) -> anyhow::Result<()> {
+    let mut magic = [0u8; 4];
+    inp.read_exact(&mut magic)?;
+    anyhow::ensure!(magic == IMAGE_MAGIC, "not a usb-wasm device image");
+
+    let mut u8_buf = [0u8; 1];
+    inp.read_exact(&mut u8_buf)?;
+    anyhow::ensure!(
+        u8_buf[0] == IMAGE_FORMAT_VERSION,
+        "unsupported image format version {}",
+        u8_buf[0]
+    );
+
+    let mut u32_buf = [0u8; 4];
+    inp.read_exact(&mut u32_buf)?;
+    let block_size = u32::from_be_bytes(u32_buf);
+    let device_block_size = device.get_properties().block_size;
+    anyhow::ensure!(
+        block_size == device_block_size,
+        "image block size {block_size} doesn't match device block size {device_block_size}"
+    );
+
+    let mut u64_buf = [0u8; 8];
+    inp.read_exact(&mut u64_buf)?;
+    let total_blocks = u64::from_be_bytes(u64_buf);
+
+    inp.read_exact(&mut u32_buf)?;
+    let group_blocks = u32::from_be_bytes(u32_buf);
+    let group_count = total_blocks.div_ceil(group_blocks as u64);
+
+    let mut tags = Vec::with_capacity(group_count as usize);
+    for _ in 0..group_count {
+        inp.read_exact(&mut u8_buf)?;
+        let kind = GroupKind::from_tag(u8_buf[0])?;
+        inp.read_exact(&mut u32_buf)?;
+        tags.push((kind, u32::from_be_bytes(u32_buf)));
+    }
+
+    for (group, (kind, payload_len)) in tags.into_iter().enumerate() {
+        let start_block = group as u64 * group_blocks as u64;
+        let blocks_in_group = group_blocks.min((total_blocks - start_block) as u32);
+
+        match kind {
+            GroupKind::AllZero => {
+                if discard_zero_groups {
+                    device.discard_blocks(start_block, blocks_in_group)?;
+                }
+            }
+            GroupKind::Raw => {
+                let mut data = vec![0u8; payload_len as usize];
+                inp.read_exact(&mut data)?;
+                device.write_blocks(start_block, blocks_in_group, &data)?;
+            }
+            GroupKind::Zstd => {
+                let mut compressed = vec![0u8; payload_len as usize];
+                inp.read_exact(&mut compressed)?;
+                let data = zstd::bulk::decompress(
+                    &compressed,
+                    blocks_in_group as usize * block_size as usize,
+                )?;
+                device.write_blocks(start_block, blocks_in_group, &data)?;
+            }
+        }
+    }
+
+    Ok(())
+}
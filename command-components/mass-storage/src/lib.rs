@@ -1,10 +1,13 @@
+use std::cell::RefCell;
 use std::io::{self, Read, Seek, Write};
+use std::rc::Rc;
 
+use async_bulk_only::TransferPolicy;
 use bulk_only::BulkOnlyTransportDevice;
 
 use chrono::{DateTime, Local};
 use fatfs::{Dir, FileSystem, FsOptions, ReadWriteSeek};
-use mass_storage::MassStorageDevice;
+use mass_storage::{CacheConfig, MassStorageDevice};
 use rand::{Fill, Rng};
 use tracing::{debug, info};
 #[cfg(target_arch = "wasm32")]
@@ -12,10 +15,51 @@ use usb_wasm_bindings::device::UsbDevice;
 
 use anyhow::anyhow;
 
+pub mod arc_cache;
+pub mod async_bulk_only;
 pub mod bulk_only;
+pub mod cached_block_device;
+pub mod ext2;
+pub mod fatfs_adapter;
+pub mod ftdi;
+pub mod image;
+pub mod lru;
 pub mod mass_storage;
+pub mod partition_table;
+pub mod serial;
+pub mod usbtmc;
+
+use cached_block_device::{CachedBlockDevice, BLOCK_SIZE, CACHE_CAPACITY_BLOCKS};
+use ext2::Ext2Filesystem;
+use fatfs_adapter::FatfsAdapter;
+
+/// Either of the two read paths `tree`/`ls`/`cat`/`write` can select between,
+/// decided once per [`get_filesystem`] call by probing the partition's
+/// superblock. `Ext2` is read-only; `write` refuses it outright rather than
+/// pretending to support it.
+enum MassStorageFilesystem {
+    Fat(FileSystem<CachedBlockDevice<FatfsAdapter>>),
+    Ext2(Ext2Filesystem<FatfsAdapter>),
+}
+
+/// Prints every partition on the selected device (index, size, type, and
+/// label), so a user can pick which one to pass to `ls`/`cat`/`tree`/`write`
+/// before committing to one.
+pub fn list_partitions() -> anyhow::Result<()> {
+    let msd = get_mass_storage_device()?;
+    for partition in FatfsAdapter::list_partitions(msd)? {
+        println!(
+            "{}. {} ({}) {}",
+            partition.index,
+            human_readable_file_size(partition.size_bytes(), 2),
+            partition.type_name,
+            partition.label.as_deref().unwrap_or(""),
+        );
+    }
+    Ok(())
+}
 
-pub fn tree(path: Option<String>) -> anyhow::Result<()> {
+pub fn tree(path: Option<String>, partition: Option<usize>) -> anyhow::Result<()> {
     fn _tree(dir: Dir<'_, impl ReadWriteSeek>, depth: usize) -> Result<Vec<String>, io::Error> {
         debug!(depth, "build_fs_tree_");
         if depth > 10 {
@@ -36,20 +80,51 @@ pub fn tree(path: Option<String>) -> anyhow::Result<()> {
         Ok(lines)
     }
 
-    let fs = get_filesystem()?;
-    let root_dir = fs.root_dir();
-    let dir = match path {
-        None => root_dir,
-        Some(ref path) if path == "." => root_dir,
-        Some(ref path) => root_dir.open_dir(path)?,
+    fn _tree_ext2(
+        fs: &Ext2Filesystem<FatfsAdapter>,
+        path: &str,
+        depth: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        if depth > 10 {
+            return Ok(vec![]);
+        }
+
+        let mut lines: Vec<String> = Vec::new();
+        for entry in fs.read_dir(path)? {
+            if entry.name.starts_with('.') {
+                continue;
+            }
+            lines.push(format!("{}|_ {}", "  ".repeat(depth), entry.name));
+            if entry.is_dir {
+                let child_path = format!("{}/{}", path.trim_end_matches('/'), entry.name);
+                lines.extend(_tree_ext2(fs, &child_path, depth + 1)?);
+            }
+        }
+        Ok(lines)
+    }
+
+    let body = match get_filesystem(partition)? {
+        MassStorageFilesystem::Fat(fs) => {
+            let root_dir = fs.root_dir();
+            let dir = match path {
+                None => root_dir,
+                Some(ref path) if path == "." => root_dir,
+                Some(ref path) => root_dir.open_dir(path)?,
+            };
+            _tree(dir, 0)?
+        }
+        MassStorageFilesystem::Ext2(fs) => {
+            let path = path.filter(|path| path != ".").unwrap_or_default();
+            _tree_ext2(&fs, &path, 0)?
+        }
     };
 
-    let lines = [vec!["\\.".to_string()], _tree(dir, 0)?].concat();
+    let lines = [vec!["\\.".to_string()], body].concat();
     println!("{}", lines.join("\n"));
     Ok(())
 }
 
-pub fn ls(dir: Option<String>) -> anyhow::Result<()> {
+pub fn ls(dir: Option<String>, partition: Option<usize>) -> anyhow::Result<()> {
     fn format_file_size(size: u64) -> String {
         const KB: u64 = 1024;
         const MB: u64 = 1024 * KB;
@@ -65,44 +140,89 @@ pub fn ls(dir: Option<String>) -> anyhow::Result<()> {
         }
     }
 
-    let fs = get_filesystem()?;
-    let root_dir = fs.root_dir();
-    let dir = match dir {
-        None => root_dir,
-        Some(ref path) if path == "." => root_dir,
-        Some(ref path) => root_dir.open_dir(path)?,
-    };
-    for r in dir.iter() {
-        let e = r?;
-        let modified = DateTime::<Local>::from(e.modified())
-            .format("%Y-%m-%d %H:%M:%S")
-            .to_string();
-        println!(
-            "{:4}  {}  {}",
-            format_file_size(e.len()),
-            modified,
-            e.file_name()
-        );
+    match get_filesystem(partition)? {
+        MassStorageFilesystem::Fat(fs) => {
+            let root_dir = fs.root_dir();
+            let dir = match dir {
+                None => root_dir,
+                Some(ref path) if path == "." => root_dir,
+                Some(ref path) => root_dir.open_dir(path)?,
+            };
+            for r in dir.iter() {
+                let e = r?;
+                let modified = DateTime::<Local>::from(e.modified())
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string();
+                println!(
+                    "{:4}  {}  {}",
+                    format_file_size(e.len()),
+                    modified,
+                    e.file_name()
+                );
+            }
+        }
+        MassStorageFilesystem::Ext2(fs) => {
+            let path = dir.filter(|path| path != ".").unwrap_or_default();
+            for entry in fs.read_dir(&path)? {
+                let modified = entry
+                    .modified
+                    .map(|modified| modified.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default();
+                println!(
+                    "{:4}  {}  {}",
+                    format_file_size(entry.size),
+                    modified,
+                    entry.name
+                );
+            }
+        }
     }
     Ok(())
 }
 
-pub fn cat(file: String) -> anyhow::Result<()> {
-    let fs = get_filesystem()?;
-    let root_dir = fs.root_dir();
-    let mut file = root_dir.open_file(&file)?;
-    let mut buf = vec![];
-    file.read_to_end(&mut buf)?;
+pub fn cat(file: String, partition: Option<usize>) -> anyhow::Result<()> {
+    let buf = match get_filesystem(partition)? {
+        MassStorageFilesystem::Fat(fs) => {
+            let mut file = fs.root_dir().open_file(&file)?;
+            let mut buf = vec![];
+            file.read_to_end(&mut buf)?;
+            buf
+        }
+        MassStorageFilesystem::Ext2(fs) => fs.read_file(&file)?,
+    };
     print!("{}", String::from_utf8_lossy(&buf));
     Ok(())
 }
 
-pub fn write(path: &str, contents: &[u8]) -> anyhow::Result<()> {
-    let fs = get_filesystem()?;
-    let mut file = fs.root_dir().create_file(path)?;
-    file.truncate()?;
-    file.write_all(contents)?;
-    Ok(())
+pub fn write(path: &str, contents: &[u8], partition: Option<usize>) -> anyhow::Result<()> {
+    match get_filesystem(partition)? {
+        MassStorageFilesystem::Fat(fs) => {
+            let mut file = fs.root_dir().create_file(path)?;
+            file.truncate()?;
+            file.write_all(contents)?;
+            Ok(())
+        }
+        MassStorageFilesystem::Ext2(_) => Err(anyhow!(
+            "writing to ext2/ext3 partitions isn't supported (read-only)"
+        )),
+    }
+}
+
+/// Dumps the whole selected mass storage device to `out_path` as a sparse,
+/// compressed image (see [`image::dump_image`]).
+pub fn dump_image(out_path: &str) -> anyhow::Result<()> {
+    let mut msd = get_mass_storage_device()?;
+    let out = std::io::BufWriter::new(std::fs::File::create(out_path)?);
+    image::dump_image(&mut msd, out)
+}
+
+/// Restores the selected mass storage device from an image previously
+/// written by [`dump_image`], optionally UNMAP-ing the regions that were
+/// all-zero at dump time (see [`image::restore_image`]).
+pub fn restore_image(in_path: &str, discard_zero_groups: bool) -> anyhow::Result<()> {
+    let mut msd = get_mass_storage_device()?;
+    let inp = std::io::BufReader::new(std::fs::File::open(in_path)?);
+    image::restore_image(&mut msd, inp, discard_zero_groups)
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -116,10 +236,33 @@ fn get_mass_storage_device() -> anyhow::Result<MassStorageDevice> {
                 let if_descriptor = interface.descriptor();
                 if_descriptor.interface_class == 0x08 && if_descriptor.interface_protocol == 0x50
             });
-            if let Some(interface) = interface {
-                let bulk_only_transport =
-                    BulkOnlyTransportDevice::new(device, configuration, interface);
-                mass_storage_devices.push(MassStorageDevice::new(bulk_only_transport).unwrap());
+            match interface {
+                Some(interface) => {
+                    let bulk_only_transport =
+                        BulkOnlyTransportDevice::new(device, configuration, interface);
+                    // Bulk-Only Transport devices (card readers, some drives)
+                    // can expose several LUNs over the same pair of bulk
+                    // endpoints, so every instance below shares one transport
+                    // and just picks a different LUN to address.
+                    let max_lun = bulk_only_transport.max_lun();
+                    let bulk_only_transport = Rc::new(RefCell::new(bulk_only_transport));
+
+                    for lun in 0..=max_lun {
+                        match MassStorageDevice::new(
+                            Rc::clone(&bulk_only_transport),
+                            lun,
+                            CacheConfig::default(),
+                        ) {
+                            Ok(msd) => mass_storage_devices.push(msd),
+                            Err(err) => {
+                                info!(lun, %err, "skipping LUN that failed to initialize")
+                            }
+                        }
+                    }
+                }
+                None => {
+                    // Not a mass storage device; leave it alone.
+                }
             }
         }
 
@@ -175,7 +318,9 @@ fn get_mass_storage_device() -> anyhow::Result<MassStorageDevice> {
             if let Some(interface) = interface {
                 let bulk_only_transport =
                     BulkOnlyTransportDevice::new(device, 0, interface.number());
-                mass_storage_devices.push(MassStorageDevice::new(bulk_only_transport).unwrap());
+                mass_storage_devices.push(
+                    MassStorageDevice::new(bulk_only_transport, CacheConfig::default()).unwrap(),
+                );
             }
         }
 
@@ -214,28 +359,24 @@ fn get_mass_storage_device() -> anyhow::Result<MassStorageDevice> {
     Ok(msd)
 }
 
-fn get_filesystem() -> anyhow::Result<FileSystem<impl ReadWriteSeek>> {
-    let mut msd = get_mass_storage_device().unwrap();
-    // let mut msd =
-    //     BufStream::with_capacities(24576, 24576, get_mass_storage_device().unwrap());
-    let mbr = mbrman::MBR::read_from(&mut msd, 512)?;
-    let (_, partition) = mbr.iter().next().ok_or(anyhow!("No partition found"))?;
-    let starting_lba = partition.starting_lba;
-    let sectors = partition.sectors;
-    let sector_size = mbr.sector_size;
-
-    println!("starting_lba: {}", starting_lba);
-    println!("sectors: {}", sectors);
-
-    let fat_slice = fscommon::StreamSlice::new(
-        msd,
-        (starting_lba * sector_size).into(),
-        (starting_lba + sectors) as u64 * sector_size as u64,
-    )
-    .unwrap();
+fn get_filesystem(partition: Option<usize>) -> anyhow::Result<MassStorageFilesystem> {
+    let msd = get_mass_storage_device().unwrap();
+    let mut adapter = FatfsAdapter::open_partition(msd, partition.unwrap_or(0))?;
+
+    // ext2/ext3 is probed for first since its superblock magic is cheap to
+    // check up front; anything else is assumed to be FAT, same as before
+    // this probe was added.
+    if ext2::probe(&mut adapter)? {
+        debug!("Initialized ext2 filesystem");
+        return Ok(MassStorageFilesystem::Ext2(Ext2Filesystem::open(adapter)?));
+    }
 
+    let cached = CachedBlockDevice::new(adapter, BLOCK_SIZE, CACHE_CAPACITY_BLOCKS);
     debug!("Initialized Filesystem");
-    Ok(FileSystem::new(fat_slice, FsOptions::new())?)
+    Ok(MassStorageFilesystem::Fat(FileSystem::new(
+        cached,
+        FsOptions::new(),
+    )?))
 }
 
 // WARNING: This will probably break your filesystem, as this function just writes random blocks to the device
@@ -267,6 +408,11 @@ pub fn benchmark_raw_speed(
         human_readable_file_size(rnd_test_size_mb as u64 * 1024 * 1024, 2),
     );
     const NUM_BLOCKS: u32 = 2048;
+    // How many READ(10)/WRITE(10) commands `benchmark_raw_speed` keeps
+    // outstanding at once, so it measures sustained throughput rather than
+    // the latency of one command's round-trip at a time.
+    const QUEUE_DEPTH: usize = 4;
+    let policy = TransferPolicy::default();
     let mut report = Report {
         sequential_write_speed: 0.0,
         sequential_read_speed: 0.0,
@@ -281,11 +427,15 @@ pub fn benchmark_raw_speed(
         let mut data = vec![0_u8; NUM_BLOCKS as usize * 512];
         data[..].try_fill(&mut rng)?;
 
-        let address = 8192;
+        let address: u64 = 8192;
         // rng.gen_range(0..properties.total_number_of_blocks - NUM_REPETITIONS * NUM_BLOCKS);
         let start_write = std::time::Instant::now();
-        for i in 0..seq_num_repetitions {
-            msd.write_blocks(address + i * NUM_BLOCKS, NUM_BLOCKS as u16, &data);
+        for batch_start in (0..seq_num_repetitions).step_by(QUEUE_DEPTH) {
+            let batch_end = (batch_start + QUEUE_DEPTH as u32).min(seq_num_repetitions);
+            let requests: Vec<(u64, &[u8])> = (batch_start..batch_end)
+                .map(|i| (address + (i * NUM_BLOCKS) as u64, data.as_slice()))
+                .collect();
+            msd.write_blocks_pipelined(&requests, NUM_BLOCKS, policy)?;
         }
         let end_write = std::time::Instant::now();
         let write_time = end_write - start_write;
@@ -294,11 +444,15 @@ pub fn benchmark_raw_speed(
     report.sequential_write_speed /= test_count as f64;
 
     for _ in 0..test_count {
-        let address = 8192;
+        let address: u64 = 8192;
         // rng.gen_range(0..properties.total_number_of_blocks - NUM_REPETITIONS * NUM_BLOCKS);
         let start_read = std::time::Instant::now();
-        for i in 0..seq_num_repetitions {
-            msd.read_blocks(address + i * NUM_BLOCKS, NUM_BLOCKS as u16);
+        for batch_start in (0..seq_num_repetitions).step_by(QUEUE_DEPTH) {
+            let batch_end = (batch_start + QUEUE_DEPTH as u32).min(seq_num_repetitions);
+            let addresses: Vec<u64> = (batch_start..batch_end)
+                .map(|i| address + (i * NUM_BLOCKS) as u64)
+                .collect();
+            msd.read_blocks_pipelined(&addresses, NUM_BLOCKS, policy)?;
         }
         let end_read = std::time::Instant::now();
         let read_time = end_read - start_read;
@@ -312,12 +466,14 @@ pub fn benchmark_raw_speed(
         let mut data = vec![0_u8; NUM_BLOCKS as usize * 512];
         data[..].try_fill(&mut rng)?;
 
-        let addresses: Vec<u32> = (0..rnd_num_repetitions)
-            .map(|_| rng.gen_range(8192..properties.total_number_of_blocks - NUM_BLOCKS))
+        let addresses: Vec<u64> = (0..rnd_num_repetitions)
+            .map(|_| rng.gen_range(8192..properties.total_number_of_blocks - NUM_BLOCKS as u64))
             .collect();
         let start_write = std::time::Instant::now();
-        for address in addresses {
-            msd.write_blocks(address, NUM_BLOCKS as u16, &data);
+        for batch in addresses.chunks(QUEUE_DEPTH) {
+            let requests: Vec<(u64, &[u8])> =
+                batch.iter().map(|&address| (address, data.as_slice())).collect();
+            msd.write_blocks_pipelined(&requests, NUM_BLOCKS, policy)?;
         }
         let end_write = std::time::Instant::now();
         let write_time = end_write - start_write;
@@ -326,12 +482,12 @@ pub fn benchmark_raw_speed(
     report.random_write_speed /= test_count as f64;
 
     for _ in 0..test_count {
-        let addresses: Vec<u32> = (0..rnd_num_repetitions)
-            .map(|_| rng.gen_range(8192..properties.total_number_of_blocks - NUM_BLOCKS))
+        let addresses: Vec<u64> = (0..rnd_num_repetitions)
+            .map(|_| rng.gen_range(8192..properties.total_number_of_blocks - NUM_BLOCKS as u64))
             .collect();
         let start_read = std::time::Instant::now();
-        for address in addresses {
-            msd.read_blocks(address, NUM_BLOCKS as u16);
+        for batch in addresses.chunks(QUEUE_DEPTH) {
+            msd.read_blocks_pipelined(batch, NUM_BLOCKS, policy)?;
         }
         let end_read = std::time::Instant::now();
         let read_time = end_read - start_read;
@@ -355,7 +511,14 @@ pub fn benchmark_raw_speed(
 }
 
 pub fn benchmark(seq_test_size_mb: usize) -> anyhow::Result<()> {
-    let fs = get_filesystem()?;
+    let fs = match get_filesystem(None)? {
+        MassStorageFilesystem::Fat(fs) => fs,
+        MassStorageFilesystem::Ext2(_) => {
+            return Err(anyhow!(
+                "benchmark writes a temp file, which isn't supported on read-only ext2/ext3 partitions"
+            ))
+        }
+    };
 
     let root_dir = fs.root_dir();
     let mut temp_file = root_dir.create_file("temp.bin")?;
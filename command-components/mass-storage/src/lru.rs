@@ -1,45 +1,57 @@
 use ahash::AHashMap;
-use std::{collections::HashMap, fmt::Debug, hash::Hash};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
 
 pub trait HashKey: Hash + PartialEq + Eq + Clone + Debug {}
 impl<T: Hash + PartialEq + Eq + Clone + Debug> HashKey for T {}
 
-struct LruEntry<V> {
-    last_used: usize,
+/// A slot in the intrusive doubly-linked list, ordered MRU (`head`) to LRU
+/// (`tail`). `prev`/`next` are indices into `LruCache::slots`, not pointers,
+/// so the list survives living inside a `Vec`.
+struct Node<K, V> {
+    key: K,
     value: V,
+    prev: Option<usize>,
+    next: Option<usize>,
+    expires_at: Option<Instant>,
 }
 
+/// A fixed-capacity LRU cache. Recency is tracked by threading a doubly-linked
+/// list through `slots` rather than a monotonic age counter, so touching an
+/// entry (`get`/`get_mut`/`set`) and evicting the LRU entry are both O(1)
+/// instead of requiring a full scan of the map.
+///
+/// Entries can optionally carry a TTL (via `default_ttl` or `set_with_ttl`)
+/// so staleness, not just capacity pressure, can evict them. When nothing in
+/// the cache ever sets a TTL, `expires_at` stays `None` everywhere and the
+/// extra checks below are a cheap `None` comparison, not a real cost.
 pub struct LruCache<K: HashKey, V: Debug> {
     capacity: usize,
-    cache: AHashMap<K, LruEntry<V>>,
-    age: usize,
+    slots: Vec<Option<Node<K, V>>>,
+    index: AHashMap<K, usize>,
+    // Slots vacated by `remove`/eviction, reused by later inserts instead of
+    // growing `slots` without bound.
+    free: Vec<usize>,
+    head: Option<usize>, // MRU
+    tail: Option<usize>, // LRU
+    default_ttl: Option<Duration>,
+    // Set once any entry has been given an expiry, so `insert` only scans
+    // for expired slots when one could exist.
+    ttl_in_use: bool,
 }
 
 impl<K: HashKey, V: Debug> LruCache<K, V> {
-    fn remove_lru_entry(&mut self) -> Option<(K, V)> {
-        if self.cache.len() == 0 {
-            return None;
-        }
-
-        let key = self
-            .cache
-            .iter()
-            .min_by_key(|(_, entry)| entry.last_used)
-            .map(|(k, _)| k)
-            .unwrap()
-            .clone();
-        let entry = self.cache.remove_entry(&key).unwrap();
-
-        // println!("Ejecting {:?}", key);
-
-        Some((entry.0, entry.1.value))
-    }
-
     pub fn new(capacity: usize) -> Self {
         LruCache {
             capacity,
-            cache: AHashMap::with_capacity(capacity),
-            age: 0,
+            slots: Vec::with_capacity(capacity),
+            index: AHashMap::with_capacity(capacity),
+            free: Vec::new(),
+            head: None,
+            tail: None,
+            default_ttl: None,
+            ttl_in_use: false,
         }
     }
 
@@ -47,74 +59,337 @@ impl<K: HashKey, V: Debug> LruCache<K, V> {
         self.capacity
     }
 
-    pub fn get(&mut self, key: &K) -> Option<&V> {
-        // println!("Cache: get({:?})", key);
-        self.age += 1;
-        let mut entry = self.cache.get_mut(key);
+    /// Sets a TTL applied to every entry inserted via `set` from now on
+    /// (entries inserted via `set_with_ttl` keep whatever TTL they were
+    /// given instead).
+    pub fn set_default_ttl(&mut self, ttl: Duration) {
+        self.default_ttl = Some(ttl);
+    }
 
-        if let Some(entry) = &mut entry {
-            entry.last_used = self.age;
+    fn is_expired(node: &Node<K, V>, now: Instant) -> bool {
+        node.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// Removes `slot` from the list without touching the map or freeing it.
+    fn unlink(&mut self, slot: usize) {
+        let (prev, next) = {
+            let node = self.slots[slot].as_ref().unwrap();
+            (node.prev, node.next)
+        };
+
+        match prev {
+            Some(p) => self.slots[p].as_mut().unwrap().next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(n) => self.slots[n].as_mut().unwrap().prev = prev,
+            None => self.tail = prev,
         }
+    }
 
-        entry.map(|e| &e.value)
+    /// Splices `slot` in as the new head (MRU).
+    fn push_front(&mut self, slot: usize) {
+        let old_head = self.head;
+        {
+            let node = self.slots[slot].as_mut().unwrap();
+            node.prev = None;
+            node.next = old_head;
+        }
+        if let Some(head) = old_head {
+            self.slots[head].as_mut().unwrap().prev = Some(slot);
+        }
+        self.head = Some(slot);
+        if self.tail.is_none() {
+            self.tail = Some(slot);
+        }
     }
 
-    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
-        // println!("Cache: get_mut({:?})", key);
-        self.age += 1;
-        let mut entry = self.cache.get_mut(key);
+    /// Moves `slot` to the front of the list, marking it most recently used.
+    fn touch(&mut self, slot: usize) {
+        if self.head == Some(slot) {
+            return;
+        }
+        self.unlink(slot);
+        self.push_front(slot);
+    }
+
+    /// Unlinks, removes from the index, and frees `slot`, returning its
+    /// key/value.
+    fn evict_slot(&mut self, slot: usize) -> (K, V) {
+        self.unlink(slot);
+        let node = self.slots[slot].take().unwrap();
+        self.index.remove(&node.key);
+        self.free.push(slot);
+        (node.key, node.value)
+    }
+
+    /// Unlinks and frees the tail (LRU) slot, returning its key/value.
+    fn evict_lru(&mut self) -> Option<(K, V)> {
+        Some(self.evict_slot(self.tail?))
+    }
+
+    /// Evicts and returns the single least-recently-used entry, if any,
+    /// regardless of whether the cache is actually over capacity. Lets a
+    /// caller shrink the cache below its nominal capacity on its own
+    /// schedule.
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        self.evict_lru()
+    }
+
+    /// Finds any one expired slot, without regard to recency, so `set` can
+    /// prefer reclaiming dead weight over evicting a still-live LRU entry.
+    fn find_expired_slot(&self, now: Instant) -> Option<usize> {
+        self.slots.iter().position(|slot| {
+            slot.as_ref()
+                .is_some_and(|node| Self::is_expired(node, now))
+        })
+    }
 
-        if let Some(entry) = &mut entry {
-            entry.last_used = self.age;
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let slot = *self.index.get(key)?;
+        if Self::is_expired(self.slots[slot].as_ref().unwrap(), Instant::now()) {
+            self.evict_slot(slot);
+            return None;
         }
+        self.touch(slot);
+        Some(&self.slots[slot].as_ref().unwrap().value)
+    }
 
-        entry.map(|e| &mut e.value)
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let slot = *self.index.get(key)?;
+        if Self::is_expired(self.slots[slot].as_ref().unwrap(), Instant::now()) {
+            self.evict_slot(slot);
+            return None;
+        }
+        self.touch(slot);
+        Some(&mut self.slots[slot].as_mut().unwrap().value)
     }
 
     // If a value had to be evicted, returns the evicted key and value
     pub fn set(&mut self, key: K, value: V) -> Option<(K, V)> {
-        // println!("Cache: set({:?}) (contains: {})", key, self.cache.contains_key(&key));
-        self.age += 1;
+        let expires_at = self.default_ttl.map(|ttl| Instant::now() + ttl);
+        self.insert(key, value, expires_at)
+    }
+
+    /// Like `set`, but the entry expires after `ttl` regardless of the
+    /// cache's `default_ttl`.
+    pub fn set_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<(K, V)> {
+        self.insert(key, value, Some(Instant::now() + ttl))
+    }
 
-        if self.cache.contains_key(&key) {
-            let entry = self.cache.get_mut(&key).unwrap();
-            entry.last_used = self.age;
-            entry.value = value;
+    fn insert(&mut self, key: K, value: V, expires_at: Option<Instant>) -> Option<(K, V)> {
+        self.ttl_in_use |= expires_at.is_some();
+        if let Some(&slot) = self.index.get(&key) {
+            let node = self.slots[slot].as_mut().unwrap();
+            node.value = value;
+            node.expires_at = expires_at;
+            self.touch(slot);
             return None;
         }
 
-        let evicted_entry = if self.len() >= self.capacity {
-            // println!("Cache: evicting");
-            self.remove_lru_entry()
+        let evicted = if self.index.len() >= self.capacity {
+            // A dead entry is a better victim than a live one, even if the
+            // live one is technically less recently used.
+            let expired = if self.ttl_in_use {
+                self.find_expired_slot(Instant::now())
+            } else {
+                None
+            };
+            match expired {
+                Some(slot) => Some(self.evict_slot(slot)),
+                None => self.evict_lru(),
+            }
         } else {
             None
         };
 
-        self.cache.insert(
-            key.clone(),
-            LruEntry {
-                value,
-                last_used: self.age,
-            },
-        );
+        let slot = match self.free.pop() {
+            Some(slot) => slot,
+            None => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+        };
+
+        self.slots[slot] = Some(Node {
+            key: key.clone(),
+            value,
+            prev: None,
+            next: None,
+            expires_at,
+        });
+        self.index.insert(key, slot);
+        self.push_front(slot);
 
-        evicted_entry
+        evicted
     }
 
     pub fn remove(&mut self, key: &K) -> Option<V> {
-        // println!("Cache: remove({:?})", key);
-        self.cache.remove(key).map(|e| e.value)
+        let slot = self.index.remove(key)?;
+        self.unlink(slot);
+        let node = self.slots[slot].take().unwrap();
+        self.free.push(slot);
+        Some(node.value)
+    }
+
+    /// Removes every entry that has expired as of `now` in one pass,
+    /// returning their key/value pairs. Useful to call opportunistically
+    /// (e.g. between USB enumerations) rather than waiting for capacity
+    /// pressure or a lookup to notice staleness.
+    pub fn sweep_expired(&mut self, now: Instant) -> Vec<(K, V)> {
+        let expired: Vec<usize> = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, slot)| {
+                slot.as_ref()
+                    .is_some_and(|node| Self::is_expired(node, now))
+                    .then_some(i)
+            })
+            .collect();
+
+        expired.into_iter().map(|slot| self.evict_slot(slot)).collect()
     }
 
     pub fn len(&self) -> usize {
-        self.cache.len()
+        self.index.len()
     }
 
     pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
-        self.cache.iter().map(|(k, v)| (k, &v.value))
+        self.slots
+            .iter()
+            .filter_map(|slot| slot.as_ref())
+            .map(|node| (&node.key, &node.value))
     }
 
     pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
-        self.cache.iter_mut().map(|(k, v)| (k, &mut v.value))
+        self.slots
+            .iter_mut()
+            .filter_map(|slot| slot.as_mut())
+            .map(|node| (&node.key, &mut node.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used_on_capacity_pressure() {
+        let mut cache = LruCache::new(2);
+        assert!(cache.set(1, "a").is_none());
+        assert!(cache.set(2, "b").is_none());
+
+        // Touching 1 makes 2 the LRU entry.
+        assert_eq!(cache.get(&1), Some(&"a"));
+
+        let evicted = cache.set(3, "c");
+        assert_eq!(evicted, Some((2, "b")));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some(&"a"));
+        assert_eq!(cache.get(&3), Some(&"c"));
+    }
+
+    #[test]
+    fn get_mut_counts_as_a_touch_too() {
+        let mut cache = LruCache::new(2);
+        cache.set(1, 10);
+        cache.set(2, 20);
+
+        *cache.get_mut(&1).unwrap() += 1;
+
+        let evicted = cache.set(3, 30);
+        assert_eq!(evicted, Some((2, 20)));
+        assert_eq!(cache.get(&1), Some(&11));
+    }
+
+    #[test]
+    fn re_inserting_an_existing_key_updates_in_place_without_evicting() {
+        let mut cache = LruCache::new(2);
+        cache.set(1, "a");
+        cache.set(2, "b");
+
+        assert!(cache.set(1, "a2").is_none());
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&1), Some(&"a2"));
+    }
+
+    #[test]
+    fn pop_lru_evicts_regardless_of_capacity_pressure() {
+        let mut cache = LruCache::new(5);
+        cache.set(1, "a");
+        cache.set(2, "b");
+
+        assert_eq!(cache.pop_lru(), Some((1, "a")));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.pop_lru(), Some((2, "b")));
+        assert_eq!(cache.pop_lru(), None);
+    }
+
+    #[test]
+    fn remove_unlinks_without_disturbing_recency_order() {
+        let mut cache = LruCache::new(3);
+        cache.set(1, "a");
+        cache.set(2, "b");
+        cache.set(3, "c");
+
+        assert_eq!(cache.remove(&2), Some("b"));
+        assert_eq!(cache.len(), 2);
+
+        // 2's slot is free for reuse; 1 is still the LRU entry.
+        let evicted = cache.set(4, "d");
+        assert_eq!(cache.len(), 3);
+        assert!(evicted.is_none());
+        let evicted = cache.set(5, "e");
+        assert_eq!(evicted, Some((1, "a")));
+    }
+
+    #[test]
+    fn entries_expire_after_their_ttl() {
+        let mut cache = LruCache::new(2);
+        cache.set_with_ttl(1, "a", Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(30));
+
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn default_ttl_applies_to_entries_inserted_via_set() {
+        let mut cache = LruCache::new(2);
+        cache.set_default_ttl(Duration::from_millis(10));
+        cache.set(1, "a");
+        std::thread::sleep(Duration::from_millis(30));
+
+        assert_eq!(cache.get_mut(&1), None);
+    }
+
+    #[test]
+    fn sweep_expired_removes_every_stale_entry_in_one_pass() {
+        let mut cache = LruCache::new(3);
+        cache.set_with_ttl(1, "a", Duration::from_millis(10));
+        cache.set(2, "b");
+        std::thread::sleep(Duration::from_millis(30));
+
+        let expired = cache.sweep_expired(Instant::now());
+        assert_eq!(expired, vec![(1, "a")]);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(&2), Some(&"b"));
+    }
+
+    #[test]
+    fn inserting_over_capacity_reclaims_an_expired_entry_before_evicting_a_live_lru_one() {
+        let mut cache = LruCache::new(2);
+        cache.set_with_ttl(1, "a", Duration::from_millis(10));
+        cache.set(2, "b");
+        std::thread::sleep(Duration::from_millis(30));
+
+        // 1 is both the LRU entry and expired; the expired one should be
+        // reclaimed even though plain LRU order would pick the same victim
+        // here too -- the point is `find_expired_slot` runs first.
+        let evicted = cache.set(3, "c");
+        assert_eq!(evicted, Some((1, "a")));
+        assert_eq!(cache.get(&2), Some(&"b"));
+        assert_eq!(cache.get(&3), Some(&"c"));
     }
 }
@@ -1,5 +1,7 @@
 use clap::{Parser, Subcommand};
-use mass_storage::{benchmark, benchmark_raw_speed, cat, ls, tree};
+use mass_storage::{
+    benchmark, benchmark_raw_speed, cat, dump_image, list_partitions, ls, restore_image, tree,
+};
 use tracing::Level;
 
 use anyhow::anyhow;
@@ -14,12 +16,26 @@ struct Args {
 
 #[derive(Subcommand, Debug)]
 enum Command {
+    #[clap(about = "List the partitions (MBR or GPT) on the device")]
+    ListPartitions,
     #[clap(about = "Print a tree of the filesystem")]
-    Tree { path: Vec<String> },
+    Tree {
+        path: Vec<String>,
+        #[arg(long)]
+        partition: Option<usize>,
+    },
     #[clap(about = "List files in the filesystem")]
-    Ls { path: Vec<String> },
+    Ls {
+        path: Vec<String>,
+        #[arg(long)]
+        partition: Option<usize>,
+    },
     #[clap(about = "Read a file from the filesystem")]
-    Cat { path: Vec<String> },
+    Cat {
+        path: Vec<String>,
+        #[arg(long)]
+        partition: Option<usize>,
+    },
     #[clap(about = "Benchmark speed of writing/reading to/from the filesystem")]
     Benchmark { megabytes: usize },
     // THIS WILL WRITE BLOCKS DIRECTLY TO THE DEVICE AND WILL DESTROY YOUR PARTITION
@@ -31,6 +47,14 @@ enum Command {
         rnd_megabytes: usize,
         samples: usize,
     },
+    #[clap(about = "Dump the device to a sparse, compressed image file")]
+    DumpImage { path: String },
+    #[clap(about = "Restore the device from an image written by dump-image")]
+    RestoreImage {
+        path: String,
+        #[arg(long)]
+        discard_zero_groups: bool,
+    },
     // TODO: Copy
 }
 
@@ -49,15 +73,24 @@ pub fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
     match args.command {
-        Command::Tree { path } => tree(vec_to_opt_str(path))?,
-        Command::Ls { path } => ls(vec_to_opt_str(path))?,
-        Command::Cat { path } => cat(vec_to_opt_str(path).ok_or(anyhow!("No file specified"))?)?,
+        Command::ListPartitions => list_partitions()?,
+        Command::Tree { path, partition } => tree(vec_to_opt_str(path), partition)?,
+        Command::Ls { path, partition } => ls(vec_to_opt_str(path), partition)?,
+        Command::Cat { path, partition } => cat(
+            vec_to_opt_str(path).ok_or(anyhow!("No file specified"))?,
+            partition,
+        )?,
         Command::Benchmark { megabytes } => benchmark(megabytes)?,
         Command::RawBenchmark {
             seq_megabytes,
             rnd_megabytes,
             samples,
         } => benchmark_raw_speed(1, seq_megabytes, rnd_megabytes, samples)?,
+        Command::DumpImage { path } => dump_image(&path)?,
+        Command::RestoreImage {
+            path,
+            discard_zero_groups,
+        } => restore_image(&path, discard_zero_groups)?,
     }
 
     Ok(())
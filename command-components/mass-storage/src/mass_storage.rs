@@ -1,8 +1,11 @@
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::cell::RefCell;
 use std::io::{self, Read, Seek, Write};
+use std::rc::Rc;
 use thiserror::Error;
 use tracing::{debug, trace};
 
+use crate::async_bulk_only::{block_on_all, AsyncBulkOnlyTransportDevice, TransferPolicy};
 use crate::bulk_only::{
     BulkOnlyTransportCommandBlock, BulkOnlyTransportDevice, CommandStatusWrapperStatus,
 };
@@ -10,45 +13,131 @@ use uluru::LRUCache;
 
 const CACHE_SIZE: usize = 128;
 
+/// How many times a command is retried after a recoverable sense (unit
+/// attention, not-ready-becoming-ready) before giving up.
+const MAX_COMMAND_RETRIES: u32 = 3;
+const COMMAND_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+
 #[derive(Debug, Error)]
 pub enum MassStorageDeviceError {
     #[error("Incompatible device")]
     IncompatibleDevice,
     #[error("Device is not ready yet")]
     NotReady,
+    #[error("Command failed")]
+    CommandFailed,
+    #[error("Command failed: {0:?}")]
+    DeviceError(SenseKey),
+    #[error("Unsupported block size: {0} (must be a non-zero power of two)")]
+    UnsupportedBlockSize(u32),
+    #[error("Medium is write-protected")]
+    WriteProtected,
+}
+
+/// Classification of the SCSI sense key returned by REQUEST SENSE, per
+/// SPC-4 table "Sense key descriptions". Only the keys this crate actually
+/// branches on are broken out; everything else falls back to `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SenseKey {
+    NoSense,
+    NotReady,
+    MediumError,
+    IllegalRequest,
+    UnitAttention,
+    Other(u8),
+}
+
+impl From<u8> for SenseKey {
+    fn from(value: u8) -> Self {
+        match value {
+            0x0 => SenseKey::NoSense,
+            0x2 => SenseKey::NotReady,
+            0x3 => SenseKey::MediumError,
+            0x5 => SenseKey::IllegalRequest,
+            0x6 => SenseKey::UnitAttention,
+            other => SenseKey::Other(other),
+        }
+    }
+}
+
+/// Structured REQUEST SENSE (fixed format) reply.
+#[derive(Debug, Clone)]
+pub struct SenseData {
+    pub sense_key: SenseKey,
+    pub information: u32,
+    pub additional_sense_code: u8,
+    pub additional_sense_code_qualifier: u8,
+}
+
+impl SenseData {
+    /// Whether a caller can reasonably retry the command that produced this
+    /// sense: a unit attention (e.g. media change) or a device that's still
+    /// spinning up / becoming ready.
+    fn is_recoverable(&self) -> bool {
+        matches!(self.sense_key, SenseKey::UnitAttention | SenseKey::NotReady)
+    }
+}
+
+/// Tunables for the block cache's read-ahead and write-back behavior.
+/// Passed to [`MassStorageDevice::new`]; the zero-value default matches the
+/// old one-block-at-a-time behavior, so callers only pay for prefetch and
+/// coalescing once they opt in.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CacheConfig {
+    /// Extra trailing blocks to fetch (bounded by device capacity and cache
+    /// size) whenever a read faults in a range, so a sequential read needs
+    /// fewer `read_blocks` round-trips.
+    pub read_ahead_blocks: u32,
+    /// Whether `flush_cache` coalesces runs of adjacent dirty cache entries
+    /// into a single multi-block WRITE(10)/WRITE(16) instead of one
+    /// `write_blocks(block, 1, ...)` call per dirty block.
+    pub write_coalesce: bool,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct MassStorageDeviceProperties {
     pub name: String,
     pub capacity: u64,
-    pub total_number_of_blocks: u32,
+    pub total_number_of_blocks: u64,
     pub block_size: u32,
+    pub thin_provisioned: bool,
+    /// The WP bit from MODE SENSE(6) page 0x3F's device-specific parameter
+    /// byte. `write`/`write_blocks` refuse to touch the device when this is
+    /// set, rather than sending a WRITE the device would just reject.
+    pub write_protected: bool,
 }
 
+/// The cache line payload is sized to the device's real `block_size` at
+/// construction time (validated in [`MassStorageDevice::new`]), rather than
+/// assuming the common 512-byte sector size; this is what makes the cache
+/// correct on 4Kn (4096-byte sector) and other Advanced Format media.
 #[derive(Debug, Clone)]
 struct CacheEntry {
-    block: u32,
-    data: [u8; 512],
+    block: u64,
+    data: Vec<u8>,
     dirty: bool,
 }
 
 impl CacheEntry {
-    fn new(block: u32, data: [u8; 512], dirty: bool) -> Self {
+    fn new(block: u64, data: Vec<u8>, dirty: bool) -> Self {
         Self { block, data, dirty }
     }
 
-    fn from_vec(block: u32, data: &[u8], dirty: bool) -> Self {
-        let mut entry = Self::new(block, [0; 512], dirty);
-        entry.data.copy_from_slice(data);
-        entry
+    fn from_vec(block: u64, data: &[u8], dirty: bool) -> Self {
+        Self::new(block, data.to_vec(), dirty)
     }
 }
 
 // Implementation of a Mass Storage USB Device using SCSI commands on top of a Bulk Only Transport USB device
 pub struct MassStorageDevice {
-    device: BulkOnlyTransportDevice,
+    // Shared rather than owned outright: several `MassStorageDevice`s (one
+    // per LUN) can address the same physical Bulk-Only Transport, so each
+    // instance re-selects `lun` before every command rather than assuming
+    // it still holds the transport's `selected_lun`.
+    device: Rc<RefCell<BulkOnlyTransportDevice>>,
+    lun: u8,
     properties: MassStorageDeviceProperties,
+    cache_config: CacheConfig,
 
     cache: LRUCache<CacheEntry, CACHE_SIZE>, // Block -> Data
     reads: usize,
@@ -60,10 +149,16 @@ pub struct MassStorageDevice {
 }
 
 impl MassStorageDevice {
-    pub fn new(device: BulkOnlyTransportDevice) -> Result<Self, MassStorageDeviceError> {
+    pub fn new(
+        device: Rc<RefCell<BulkOnlyTransportDevice>>,
+        lun: u8,
+        cache_config: CacheConfig,
+    ) -> Result<Self, MassStorageDeviceError> {
         let mut mass_storage_device = MassStorageDevice {
             device,
+            lun,
             properties: Default::default(),
+            cache_config,
             cache: LRUCache::default(),
             cursor: 0,
             reads: 0,
@@ -73,26 +168,39 @@ impl MassStorageDevice {
         };
 
         // Inquiry properties
-        if !mass_storage_device.test_unit_ready() {
-            return Err(MassStorageDeviceError::NotReady);
-        }
+        mass_storage_device.test_unit_ready()?;
 
-        let inquiry = mass_storage_device.inquiry();
-        let capacity = mass_storage_device.read_capacity();
+        let inquiry = mass_storage_device.inquiry()?;
+        let capacity = mass_storage_device.read_capacity()?;
 
         if inquiry.peripheral_qualifier != 0 && inquiry.peripheral_device_type != 0 {
             return Err(MassStorageDeviceError::IncompatibleDevice);
         }
 
+        // Not every device supports MODE SENSE page 0x3F; default to
+        // not-write-protected rather than failing enumeration over it.
+        let write_protected = mass_storage_device.is_write_protected().unwrap_or(false);
+
         let name = format!("{} {}", inquiry.vendor_id, inquiry.product_id);
 
         let properties = MassStorageDeviceProperties {
             name,
-            capacity: capacity.block_length_in_bytes as u64
-                * capacity.returned_logical_block_address as u64,
+            capacity: capacity.capacity_in_bytes,
             block_size: capacity.block_length_in_bytes,
-            total_number_of_blocks: capacity.returned_logical_block_address,
+            total_number_of_blocks: capacity.returned_logical_block_address + 1,
+            thin_provisioned: capacity.thin_provisioned,
+            write_protected,
         };
+
+        // The cache and the read/write path size every buffer off this
+        // value, so a block size we can't cleanly divide by would corrupt
+        // data rather than just read slow.
+        if !properties.block_size.is_power_of_two() {
+            return Err(MassStorageDeviceError::UnsupportedBlockSize(
+                properties.block_size,
+            ));
+        }
+
         mass_storage_device.properties = properties;
 
         Ok(mass_storage_device)
@@ -103,112 +211,423 @@ impl MassStorageDevice {
     }
 
     pub fn flush_cache(&mut self) {
-        let mut entries_to_write = Vec::<(u32, [u8; 512])>::new();
-        self.cache.iter().for_each(|entry| {
-            if entry.dirty {
-                entries_to_write.push((entry.block, entry.data));
-            }
-        });
+        let mut dirty_entries: Vec<(u64, Vec<u8>)> = self
+            .cache
+            .iter()
+            .filter(|entry| entry.dirty)
+            .map(|entry| (entry.block, entry.data.clone()))
+            .collect();
+        dirty_entries.sort_by_key(|(block, _)| *block);
+
+        if self.cache_config.write_coalesce {
+            let mut i = 0;
+            while i < dirty_entries.len() {
+                let run_start = dirty_entries[i].0;
+                let mut j = i + 1;
+                while j < dirty_entries.len() && dirty_entries[j].0 == dirty_entries[j - 1].0 + 1 {
+                    j += 1;
+                }
 
-        for (block, data) in entries_to_write {
-            self.write_blocks(block, 1, &data);
+                let mut data = Vec::with_capacity(dirty_entries[i..j].iter().map(|(_, d)| d.len()).sum());
+                for (_, entry_data) in &dirty_entries[i..j] {
+                    data.extend_from_slice(entry_data);
+                }
+                let blocks = (j - i) as u32;
+                if let Err(err) = self.write_blocks(run_start, blocks, &data) {
+                    tracing::warn!(block = run_start, blocks, %err, "failed to flush dirty cache run");
+                }
+
+                i = j;
+            }
+        } else {
+            for (block, data) in dirty_entries {
+                if let Err(err) = self.write_blocks(block, 1, &data) {
+                    tracing::warn!(block, %err, "failed to flush dirty cache entry");
+                }
+            }
         }
 
         self.cache.clear();
     }
 
+    /// Borrows the shared transport, re-selecting this volume's LUN first
+    /// since a sibling `MassStorageDevice` for a different LUN may have
+    /// changed it since our last command.
+    fn with_device<T>(&self, f: impl FnOnce(&mut BulkOnlyTransportDevice) -> T) -> T {
+        let mut device = self.device.borrow_mut();
+        device.select_lun(self.lun).unwrap();
+        f(&mut device)
+    }
+
+    /// Runs `attempt` (one SCSI command) up to [`MAX_COMMAND_RETRIES`] extra
+    /// times, draining sense after every failure so a unit attention (e.g.
+    /// media change) or a device that's still becoming ready doesn't abort
+    /// the whole operation. Any other sense key is returned as
+    /// [`MassStorageDeviceError::DeviceError`] immediately.
+    fn retry_on_recoverable_sense<T>(
+        &mut self,
+        mut attempt: impl FnMut(&mut Self) -> Option<T>,
+    ) -> Result<T, MassStorageDeviceError> {
+        for retry in 0..=MAX_COMMAND_RETRIES {
+            if let Some(value) = attempt(self) {
+                return Ok(value);
+            }
+
+            let sense = self.request_sense();
+            if retry == MAX_COMMAND_RETRIES || !sense.is_recoverable() {
+                return Err(MassStorageDeviceError::DeviceError(sense.sense_key));
+            }
+            std::thread::sleep(COMMAND_RETRY_DELAY);
+        }
+        unreachable!()
+    }
+
     // SCSI commands
-    pub fn test_unit_ready(&mut self) -> bool {
-        // We'll assume LUN 0
-        let cbw = BulkOnlyTransportCommandBlock {
-            command_block: vec![0x00; 6],
-            transfer_length: 0,
-        };
+    pub fn test_unit_ready(&mut self) -> Result<(), MassStorageDeviceError> {
+        self.retry_on_recoverable_sense(|device| {
+            // Addressed to `self.lun`, which `with_device` selects
+            let cbw = BulkOnlyTransportCommandBlock {
+                command_block: vec![0x00; 6],
+                transfer_length: 0,
+            };
 
-        let csw = self.device.command_out(cbw, None).unwrap();
+            let csw = device.with_device(|d| d.command_out(cbw, None)).unwrap();
+            (csw.status == CommandStatusWrapperStatus::CommandPassed).then_some(())
+        })
+    }
+
+    pub fn inquiry(&mut self) -> Result<InquiryResponse, MassStorageDeviceError> {
+        self.retry_on_recoverable_sense(|device| {
+            let cbw = BulkOnlyTransportCommandBlock {
+                command_block: vec![0x12, 0x00, 0x00, 0x00, 36, 0x00],
+                transfer_length: 36,
+            };
 
-        csw.status == CommandStatusWrapperStatus::CommandPassed
+            let (csw, data) = device.with_device(|d| d.command_in(cbw)).unwrap();
+            (csw.status == CommandStatusWrapperStatus::CommandPassed)
+                .then(|| InquiryResponse::from_bytes(&data))
+        })
     }
 
-    pub fn inquiry(&mut self) -> InquiryResponse {
-        let cbw = BulkOnlyTransportCommandBlock {
-            command_block: vec![0x12, 0x00, 0x00, 0x00, 36, 0x00],
-            transfer_length: 36,
-        };
+    /// Issues MODE SENSE(6) for page 0x3F ("return all pages") and reads
+    /// just the mode parameter header, whose device-specific parameter byte
+    /// carries the write-protect (WP) bit for direct-access devices.
+    pub fn is_write_protected(&mut self) -> Result<bool, MassStorageDeviceError> {
+        self.retry_on_recoverable_sense(|device| {
+            let cbw = BulkOnlyTransportCommandBlock {
+                command_block: vec![0x1A, 0x00, 0x3F, 0x00, 4, 0x00],
+                transfer_length: 4,
+            };
 
-        let (csw, data) = self.device.command_in(cbw).unwrap();
-        if csw.status != CommandStatusWrapperStatus::CommandPassed {
-            todo!("Handle command failure")
-        }
+            let (csw, data) = device.with_device(|d| d.command_in(cbw)).unwrap();
+            (csw.status == CommandStatusWrapperStatus::CommandPassed)
+                .then(|| (data[2] & 0b1000_0000) != 0)
+        })
+    }
+
+    /// Issues READ CAPACITY (16) (opcode 0x9E, service action 0x10), which
+    /// unlike READ CAPACITY (10) reports a 64-bit last LBA and so doesn't
+    /// silently wrap on devices larger than 2 TiB.
+    pub fn read_capacity(&mut self) -> Result<ReadCapacityResponse, MassStorageDeviceError> {
+        self.retry_on_recoverable_sense(|device| {
+            let mut command_block = BytesMut::new();
+            command_block.put_u8(0x9E); // OPCODE (SERVICE ACTION IN(16))
+            command_block.put_u8(0x10); // SERVICE ACTION: READ CAPACITY (16)
+            command_block.put_u64(0); // LOGICAL BLOCK ADDRESS
+            command_block.put_u32(32); // ALLOCATION LENGTH
+            command_block.put_u8(0); // Reserved
+            command_block.put_u8(0); // CONTROL
+            let command_block = command_block.to_vec();
+
+            let cbw = BulkOnlyTransportCommandBlock {
+                command_block,
+                transfer_length: 32,
+            };
 
-        InquiryResponse::from_bytes(&data)
+            let (csw, data) = device.with_device(|d| d.command_in(cbw)).unwrap();
+            (csw.status == CommandStatusWrapperStatus::CommandPassed)
+                .then(|| ReadCapacityResponse::from_bytes(&data))
+        })
     }
 
-    pub fn read_capacity(&mut self) -> ReadCapacityResponse {
-        let cbw = BulkOnlyTransportCommandBlock {
-            command_block: vec![0x25, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0],
-            transfer_length: 8,
-        };
+    /// Builds the CBW for a READ(10)/READ(16), routing through READ(16)
+    /// (opcode 0x88) instead of READ(10) whenever `address` doesn't fit in
+    /// 32 bits. Shared by [`Self::read_blocks`] and
+    /// [`Self::read_blocks_pipelined`] so both build the exact same CDB.
+    fn read_command_block(
+        address: u64,
+        blocks: u32,
+        block_size: u32,
+    ) -> BulkOnlyTransportCommandBlock {
+        let mut command_block = BytesMut::new();
+        if address > u32::MAX as u64 {
+            command_block.put_u8(0x88); // OPCODE: READ(16)
+            command_block.put_u8(0); // Fields I don't care about
+            command_block.put_u64(address); // Logical block address
+            command_block.put_u32(blocks); // Number of blocks to transfer
+            command_block.put_u8(0); // Group number
+            command_block.put_u8(0); // CONTROL
+        } else {
+            command_block.put_u8(0x28); // OPCODE: READ(10)
+            command_block.put_u8(0); // Fields I don't care about
+            command_block.put_u32(address as u32); // Logical block address
+            command_block.put_u8(0); // Fields I don't care about
+            command_block.put_u16(blocks as u16); // Number of blocks to transfer
+            command_block.put_u8(0); // CONTROL
+        }
 
-        let (csw, data) = self.device.command_in(cbw).unwrap();
-        if csw.status != CommandStatusWrapperStatus::CommandPassed {
-            todo!("Handle command failure")
+        BulkOnlyTransportCommandBlock {
+            command_block: command_block.to_vec(),
+            transfer_length: blocks * block_size,
+        }
+    }
+
+    /// Builds the CBW for a WRITE(10)/WRITE(16), routing through WRITE(16)
+    /// (opcode 0x8A) instead of WRITE(10) whenever `address` doesn't fit in
+    /// 32 bits. Shared by [`Self::write_blocks`] and
+    /// [`Self::write_blocks_pipelined`] so both build the exact same CDB.
+    fn write_command_block(
+        address: u64,
+        blocks: u32,
+        block_size: u32,
+    ) -> BulkOnlyTransportCommandBlock {
+        let mut command_block = BytesMut::new();
+        if address > u32::MAX as u64 {
+            command_block.put_u8(0x8A); // OPCODE: WRITE(16)
+            command_block.put_u8(0); // Fields I don't care about
+            command_block.put_u64(address); // Logical block address
+            command_block.put_u32(blocks); // Number of blocks to transfer
+            command_block.put_u8(0); // Group number
+            command_block.put_u8(0); // CONTROL
+        } else {
+            command_block.put_u8(0x2A); // OPCODE: WRITE(10)
+            command_block.put_u8(0); // Fields I don't care about
+            command_block.put_u32(address as u32); // Logical block address
+            command_block.put_u8(0); // Fields I don't care about
+            command_block.put_u16(blocks as u16); // Number of blocks to transfer
+            command_block.put_u8(0); // CONTROL
         }
 
-        ReadCapacityResponse::from_bytes(&data)
+        BulkOnlyTransportCommandBlock {
+            command_block: command_block.to_vec(),
+            transfer_length: blocks * block_size,
+        }
     }
 
-    pub fn read_blocks(&mut self, address: u32, blocks: u16) -> Vec<u8> {
+    /// Reads `blocks` logical blocks starting at `address`.
+    pub fn read_blocks(
+        &mut self,
+        address: u64,
+        blocks: u32,
+    ) -> Result<Vec<u8>, MassStorageDeviceError> {
         self.blocks_read += blocks as usize;
         self.reads += 1;
-        // println!("Reading {} block(s) starting at block {}", blocks, address);
+        let block_size = self.properties.block_size;
+
+        self.retry_on_recoverable_sense(|device| {
+            let cbw = Self::read_command_block(address, blocks, block_size);
+            let (csw, data) = device.with_device(|d| d.command_in(cbw)).unwrap();
+            (csw.status == CommandStatusWrapperStatus::CommandPassed).then_some(data)
+        })
+    }
+
+    /// Writes `data` as `blocks` logical blocks starting at `address`.
+    pub fn write_blocks(
+        &mut self,
+        address: u64,
+        blocks: u32,
+        data: &[u8],
+    ) -> Result<(), MassStorageDeviceError> {
+        if self.properties.write_protected {
+            return Err(MassStorageDeviceError::WriteProtected);
+        }
+
+        self.blocks_written += blocks as usize;
+        self.writes += 1;
+        let block_size = self.properties.block_size;
+
+        self.retry_on_recoverable_sense(|device| {
+            let cbw = Self::write_command_block(address, blocks, block_size);
+            let csw = device
+                .with_device(|d| d.command_out(cbw, Some(data)))
+                .unwrap();
+            (csw.status == CommandStatusWrapperStatus::CommandPassed).then_some(())
+        })
+    }
+
+    /// Reads `blocks` logical blocks from each address in `addresses`,
+    /// submitting every READ(10)/READ(16) up front via
+    /// [`AsyncBulkOnlyTransportDevice`] instead of waiting on each command's
+    /// full round-trip before sending the next -- the shape a sustained
+    /// sequential/random-read benchmark needs to measure throughput instead
+    /// of latency. Unlike [`Self::read_blocks`], a failed command here isn't
+    /// retried against a freshly drained sense: pipelining several commands
+    /// at once and recovering one of them via REQUEST SENSE would
+    /// desynchronize the others' view of the transport, so a caller that
+    /// needs that guarantee should fall back to `read_blocks`.
+    pub fn read_blocks_pipelined(
+        &mut self,
+        addresses: &[u64],
+        blocks: u32,
+        policy: TransferPolicy,
+    ) -> Result<Vec<Vec<u8>>, MassStorageDeviceError> {
+        self.blocks_read += blocks as usize * addresses.len();
+        self.reads += addresses.len();
+        let block_size = self.properties.block_size;
+
+        self.device.borrow_mut().select_lun(self.lun).unwrap();
+        let transport = AsyncBulkOnlyTransportDevice::new(self.device.clone(), policy);
+        let futures: Vec<_> = addresses
+            .iter()
+            .map(|&address| transport.command_in(Self::read_command_block(address, blocks, block_size)))
+            .collect();
+
+        let mut results = Vec::with_capacity(addresses.len());
+        for outcome in block_on_all(futures) {
+            let (csw, data) = outcome.map_err(|_| MassStorageDeviceError::CommandFailed)?;
+            if csw.status != CommandStatusWrapperStatus::CommandPassed {
+                return Err(MassStorageDeviceError::CommandFailed);
+            }
+            results.push(data);
+        }
+        Ok(results)
+    }
+
+    /// Writes each `(address, data)` pair in `requests` as `blocks` logical
+    /// blocks, submitting every WRITE(10)/WRITE(16) up front the same way
+    /// [`Self::read_blocks_pipelined`] does. See that method's doc comment
+    /// for why this skips the single-command sense-retry loop.
+    pub fn write_blocks_pipelined(
+        &mut self,
+        requests: &[(u64, &[u8])],
+        blocks: u32,
+        policy: TransferPolicy,
+    ) -> Result<(), MassStorageDeviceError> {
+        if self.properties.write_protected {
+            return Err(MassStorageDeviceError::WriteProtected);
+        }
+
+        self.blocks_written += blocks as usize * requests.len();
+        self.writes += requests.len();
+        let block_size = self.properties.block_size;
+
+        self.device.borrow_mut().select_lun(self.lun).unwrap();
+        let transport = AsyncBulkOnlyTransportDevice::new(self.device.clone(), policy);
+        let futures: Vec<_> = requests
+            .iter()
+            .map(|&(address, data)| {
+                transport.command_out(
+                    Self::write_command_block(address, blocks, block_size),
+                    data.to_vec(),
+                )
+            })
+            .collect();
+
+        for outcome in block_on_all(futures) {
+            let csw = outcome.map_err(|_| MassStorageDeviceError::CommandFailed)?;
+            if csw.status != CommandStatusWrapperStatus::CommandPassed {
+                return Err(MassStorageDeviceError::CommandFailed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Issues SCSI UNMAP (0x42) to tell the device that `blocks` blocks
+    /// starting at `address` are no longer needed, so a thin-provisioned
+    /// device can reclaim the backing space. Returns
+    /// [`MassStorageDeviceError::IncompatibleDevice`] if the device isn't
+    /// thin provisioned.
+    pub fn discard_blocks(
+        &mut self,
+        address: u64,
+        blocks: u32,
+    ) -> Result<(), MassStorageDeviceError> {
+        if !self.properties.thin_provisioned {
+            return Err(MassStorageDeviceError::IncompatibleDevice);
+        }
+        if self.properties.write_protected {
+            return Err(MassStorageDeviceError::WriteProtected);
+        }
+
+        let descriptor_length: u16 = 16;
+        let mut parameter_list = BytesMut::new();
+        parameter_list.put_u16(6 + descriptor_length); // UNMAP data length
+        parameter_list.put_u16(descriptor_length); // UNMAP block descriptor data length
+        parameter_list.put_u32(0); // Reserved
+        parameter_list.put_u64(address); // LBA
+        parameter_list.put_u32(blocks); // Number of blocks
+        parameter_list.put_u32(0); // Reserved
+        let parameter_list = parameter_list.to_vec();
+
         let mut command_block = BytesMut::new();
-        command_block.put_u8(0x28); // OPCODE
-        command_block.put_u8(0); // Fields I don't care about
-        command_block.put_u32(address); // Logical block address
-        command_block.put_u8(0); // Fields I don't care about
-        command_block.put_u16(blocks); // Number of blocks to transfer
+        command_block.put_u8(0x42); // OPCODE
+        command_block.put_u8(0); // Anchor / reserved
+        command_block.put_u32(0); // Reserved
+        command_block.put_u8(0); // Group number
+        command_block.put_u16(parameter_list.len() as u16); // Parameter list length
         command_block.put_u8(0); // CONTROL
         let command_block = command_block.to_vec();
 
         let cbw = BulkOnlyTransportCommandBlock {
             command_block,
-            transfer_length: blocks as u32 * self.properties.block_size,
+            transfer_length: parameter_list.len() as u32,
         };
 
-        let (csw, data) = self.device.command_in(cbw).unwrap();
+        let csw = self
+            .with_device(|d| d.command_out(cbw, Some(&parameter_list)))
+            .unwrap();
         if csw.status != CommandStatusWrapperStatus::CommandPassed {
-            todo!("Handle command failure")
+            self.request_sense();
+            return Err(MassStorageDeviceError::CommandFailed);
         }
 
-        data
+        Ok(())
     }
 
-    pub fn write_blocks(&mut self, address: u32, blocks: u16, data: &[u8]) {
-        self.blocks_written += blocks as usize;
-        self.writes += 1;
-        // println!("Writing {} blocks at address {:x}", blocks, address);
+    /// Issues WRITE SAME (10) (0x41) with the UNMAP bit set, asking the
+    /// device to zero-fill `blocks` blocks starting at `address` without
+    /// streaming the zeros over the bulk pipe. Returns
+    /// [`MassStorageDeviceError::IncompatibleDevice`] if the device isn't
+    /// thin provisioned (and so isn't guaranteed to honor the UNMAP bit).
+    pub fn write_same_zeroes(
+        &mut self,
+        address: u32,
+        blocks: u16,
+    ) -> Result<(), MassStorageDeviceError> {
+        if !self.properties.thin_provisioned {
+            return Err(MassStorageDeviceError::IncompatibleDevice);
+        }
+        if self.properties.write_protected {
+            return Err(MassStorageDeviceError::WriteProtected);
+        }
+
         let mut command_block = BytesMut::new();
-        command_block.put_u8(0x2A); // OPCODE
-        command_block.put_u8(0); // Fields I don't care about
-        command_block.put_u32(address); // Logical block address
-        command_block.put_u8(0); // Fields I don't care about
-        command_block.put_u16(blocks); // Number of blocks to transfer
+        command_block.put_u8(0x41); // OPCODE
+        command_block.put_u8(0b0000_1000); // UNMAP bit
+        command_block.put_u32(address); // LBA
+        command_block.put_u8(0); // Group number
+        command_block.put_u16(blocks); // Number of blocks
         command_block.put_u8(0); // CONTROL
         let command_block = command_block.to_vec();
 
         let cbw = BulkOnlyTransportCommandBlock {
             command_block,
-            transfer_length: blocks as u32 * self.properties.block_size,
+            transfer_length: 0,
         };
 
-        let csw = self.device.command_out(cbw, Some(data)).unwrap();
+        let csw = self.with_device(|d| d.command_out(cbw, None)).unwrap();
         if csw.status != CommandStatusWrapperStatus::CommandPassed {
             self.request_sense();
+            return Err(MassStorageDeviceError::CommandFailed);
         }
+
+        Ok(())
     }
 
-    pub fn request_sense(&mut self) {
+    /// Issues REQUEST SENSE and parses the fixed-format sense data, so
+    /// callers can tell a transient unit attention / not-ready condition
+    /// apart from a hard failure instead of just logging and giving up.
+    pub fn request_sense(&mut self) -> SenseData {
         let mut command_block = BytesMut::new();
         command_block.put_u8(0x03);
         command_block.put_u8(0x00);
@@ -222,41 +641,34 @@ impl MassStorageDevice {
             transfer_length: 252,
         };
 
-        let (csw, data) = self.device.command_in(cbw).unwrap();
+        let (csw, data) = self.with_device(|d| d.command_in(cbw)).unwrap();
         if csw.status != CommandStatusWrapperStatus::CommandPassed {
-            todo!("Handle command failure")
+            // REQUEST SENSE itself isn't expected to fail; if it does we
+            // have no diagnostic information to give the caller beyond that.
+            return SenseData {
+                sense_key: SenseKey::Other(0xFF),
+                information: 0,
+                additional_sense_code: 0,
+                additional_sense_code_qualifier: 0,
+            };
         }
 
         let mut bytes: bytes::Bytes = data.into();
-        let valid_and_response_code = bytes.get_u8();
-        let valid = valid_and_response_code & 0b10000000;
-        let response_code = valid_and_response_code & 0b01111111;
+        bytes.advance(2); // VALID + RESPONSE CODE, SEGMENT NUMBER
 
-        bytes.advance(1);
-
-        let sense_key = bytes.get_u8() & 0b00001111;
+        let sense_key = SenseKey::from(bytes.get_u8() & 0b0000_1111);
         let information = bytes.get_u32();
-        let additional_sense_length = bytes.get_u8();
-
-        let command_specific_information = bytes.get_u32();
+        bytes.advance(5); // ADDITIONAL SENSE LENGTH, COMMAND-SPECIFIC INFORMATION
 
         let additional_sense_code = bytes.get_u8();
         let additional_sense_code_qualifier = bytes.get_u8();
 
-        println!("valid: {}", valid);
-        println!("response_code: {:x?}", response_code);
-        println!("sense_key: {:x?}", sense_key);
-        println!("information: {:x?}", information);
-        println!("additional_sense_length: {:x?}", additional_sense_length);
-        println!(
-            "command_specific_information: {:x?}",
-            command_specific_information
-        );
-        println!("additional_sense_code: {:x?}", additional_sense_code);
-        println!(
-            "additional_sense_code_qualifier: {:x?}",
-            additional_sense_code_qualifier
-        );
+        SenseData {
+            sense_key,
+            information,
+            additional_sense_code,
+            additional_sense_code_qualifier,
+        }
     }
 }
 
@@ -287,6 +699,7 @@ impl Read for MassStorageDevice {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         trace!("Reading {} bytes at address {:x}", buf.len(), self.cursor);
 
+        let block_size = self.properties.block_size as usize;
         let start_address = self.cursor as usize;
         let end_address = (self.cursor + buf.len() as u64).min(self.properties.capacity) as usize; // Not-inclusive
         let num_bytes = end_address.saturating_sub(start_address);
@@ -297,11 +710,11 @@ impl Read for MassStorageDevice {
         }
 
         // First find which blocks we need to read
-        let start_block = (start_address / self.properties.block_size as usize) as u32;
-        let offset_in_start_block = start_address % self.properties.block_size as usize;
-        let end_block = ((end_address - 1) / self.properties.block_size as usize) as u32; // Because end_address is not inclusive
+        let start_block = (start_address / block_size) as u64;
+        let offset_in_start_block = start_address % block_size;
+        let end_block = ((end_address - 1) / block_size) as u64; // Because end_address is not inclusive
         let num_blocks = (end_block - start_block + 1) as usize;
-        let offset_in_end_block = ((end_address - 1) % self.properties.block_size as usize) + 1;
+        let offset_in_end_block = ((end_address - 1) % block_size) + 1;
 
         trace!(
             "Reading {} block(s) starting at block {}",
@@ -309,8 +722,8 @@ impl Read for MassStorageDevice {
             start_block
         );
 
-        let mut new_range: Option<u32> = None;
-        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        let mut new_range: Option<u64> = None;
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
 
         for block in start_block..end_block + 1 {
             if let Some(entry) = self.cache.find(|item| item.block == block) {
@@ -318,16 +731,16 @@ impl Read for MassStorageDevice {
                 if block == start_block && block == end_block {
                     buf[..].copy_from_slice(&entry.data[offset_in_start_block..offset_in_end_block]);
                 } else if block == start_block {
-                    buf[..512 - offset_in_start_block]
+                    buf[..block_size - offset_in_start_block]
                         .copy_from_slice(&entry.data[offset_in_start_block..]);
                 } else if block == end_block {
-                    buf[(512 - offset_in_start_block) + (num_blocks as usize - 2) * 512..]
+                    buf[(block_size - offset_in_start_block) + (num_blocks - 2) * block_size..]
                         .copy_from_slice(&entry.data[..offset_in_end_block]);
                 } else {
-                    buf[(512 - offset_in_start_block) + ((start_block - block) as usize) * 512
-                        ..(512 - offset_in_start_block)
-                            + ((start_block - block) as usize) * 512
-                            + 512]
+                    buf[(block_size - offset_in_start_block) + ((start_block - block) as usize) * block_size
+                        ..(block_size - offset_in_start_block)
+                            + ((start_block - block) as usize) * block_size
+                            + block_size]
                         .copy_from_slice(&entry.data);
                 }
 
@@ -358,15 +771,23 @@ impl Read for MassStorageDevice {
         );
 
         for (start, end) in ranges {
-            let data = self.read_blocks(start_block as _, (end - start) as _);
+            // Extend the fetch past `end` by the configured read-ahead
+            // window, capped at the cache's capacity (no point prefetching
+            // more than the cache can hold) and at the device's last block.
+            let read_ahead = (self.cache_config.read_ahead_blocks as u64).min(CACHE_SIZE as u64);
+            let fetch_end = (end + read_ahead).min(self.properties.total_number_of_blocks);
+
+            let data = self
+                .read_blocks(start, (fetch_end - start) as _)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
             // Put the data into the cache
-            let range = if data.len() > 512 * CACHE_SIZE {
-                data.len() - (512 * CACHE_SIZE)..data.len()
+            let range = if data.len() > block_size * CACHE_SIZE {
+                data.len() - (block_size * CACHE_SIZE)..data.len()
             } else {
                 0..data.len()
             };
-            for (i, chunk) in data[range].chunks(512).enumerate() {
-                let block = start + i as u32;
+            for (i, chunk) in data[range].chunks(block_size).enumerate() {
+                let block = start + i as u64;
 
                 if let Some(item) = self.cache.find(|item| item.block == block) {
                     item.data.copy_from_slice(&chunk);
@@ -375,29 +796,34 @@ impl Read for MassStorageDevice {
                     if let Some(evicted_entry) = self.cache.insert(value) {
                         if evicted_entry.dirty {
                             println!("Flushing block {}", block);
-                            self.write_blocks(block, 1, &evicted_entry.data);
+                            if let Err(err) = self.write_blocks(evicted_entry.block, 1, &evicted_entry.data) {
+                                tracing::warn!(block = evicted_entry.block, %err, "failed to flush evicted cache entry");
+                            }
                         }
                     }
                 }
             }
+            // Only the blocks up to `end` go into `buf`; the read-ahead tail
+            // was fetched for the cache alone.
+            let data = &data[..((end - start) as usize) * block_size];
             // Copy the data into the buffer
             if start == start_block && end == end_block + 1 {
                 buf[..].copy_from_slice(
                     &data[offset_in_start_block
-                        ..(num_blocks as usize - 1) * 512 + offset_in_end_block],
+                        ..(num_blocks - 1) * block_size + offset_in_end_block],
                 );
             } else if start == start_block {
-                buf[..(512 - offset_in_start_block) + ((end - start_block - 1) as usize) * 512]
-                    .copy_from_slice(&data[offset_in_start_block..(num_blocks as usize - 1) * 512]);
+                buf[..(block_size - offset_in_start_block) + ((end - start_block - 1) as usize) * block_size]
+                    .copy_from_slice(&data[offset_in_start_block..(num_blocks - 1) * block_size]);
             } else if end == end_block + 1 {
-                buf[(512 - offset_in_start_block) + ((start - start_block - 1) as usize) * 512..]
+                buf[(block_size - offset_in_start_block) + ((start - start_block - 1) as usize) * block_size..]
                     .copy_from_slice(
-                        &data[..(num_blocks as usize - 1) * 512 + offset_in_end_block],
+                        &data[..(num_blocks - 1) * block_size + offset_in_end_block],
                     );
             } else {
                 // General case
-                buf[(512 - offset_in_start_block) + ((start - start_block - 1) as usize) * 512
-                    ..(512 - offset_in_start_block) + ((end - start_block - 1) as usize) * 512]
+                buf[(block_size - offset_in_start_block) + ((start - start_block - 1) as usize) * block_size
+                    ..(block_size - offset_in_start_block) + ((end - start_block - 1) as usize) * block_size]
                     .copy_from_slice(&data[..]);
             }
         }
@@ -412,6 +838,14 @@ impl Write for MassStorageDevice {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         // println!("Writing {} bytes at address {:x}", buf.len(), self.cursor);
 
+        if self.properties.write_protected {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                MassStorageDeviceError::WriteProtected,
+            ));
+        }
+
+        let block_size = self.properties.block_size as usize;
         let start_address = self.cursor as usize;
         let end_address = (self.cursor + buf.len() as u64).min(self.properties.capacity) as usize; // Not-inclusive
         let num_bytes = end_address.saturating_sub(start_address);
@@ -422,10 +856,10 @@ impl Write for MassStorageDevice {
         }
 
         // First find which blocks we need to read
-        let start_block = (start_address / self.properties.block_size as usize) as u32;
-        let offset_in_start_block = start_address % self.properties.block_size as usize;
-        let end_block = ((end_address - 1) / self.properties.block_size as usize) as u32; // Because end_address is not inclusive
-        let num_blocks: u16 = (end_block - start_block + 1) as _;
+        let start_block = (start_address / block_size) as u64;
+        let offset_in_start_block = start_address % block_size;
+        let end_block = ((end_address - 1) / block_size) as u64; // Because end_address is not inclusive
+        let num_blocks: u32 = (end_block - start_block + 1) as _;
 
         tracing::trace!(
             start_address,
@@ -445,11 +879,13 @@ impl Write for MassStorageDevice {
         );
 
         if num_blocks == 1 {
-            let mut data = vec![0_u8; 512];
+            let mut data = vec![0_u8; block_size];
             if let Some(block) = self.cache.find(|item| item.block == start_block) {
                 data.copy_from_slice(&block.data);
             } else {
-                let original_data = self.read_blocks(start_block, 1);
+                let original_data = self
+                    .read_blocks(start_block, 1)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
                 data.copy_from_slice(&original_data);
             }
             data[offset_in_start_block..offset_in_start_block + buf.len()].copy_from_slice(buf);
@@ -461,44 +897,56 @@ impl Write for MassStorageDevice {
                 let value = CacheEntry::from_vec(start_block, &data, false);
                 if let Some(evicted_entry) = self.cache.insert(value) {
                     if evicted_entry.dirty {
-                        self.write_blocks(start_block, 1, &evicted_entry.data);
+                        if let Err(err) = self.write_blocks(evicted_entry.block, 1, &evicted_entry.data) {
+                            tracing::warn!(block = evicted_entry.block, %err, "failed to flush evicted cache entry");
+                        }
                     }
                 }
             }
         } else {
-            let mut data = vec![0_u8; num_blocks as usize * 512];
+            let mut data = vec![0_u8; num_blocks as usize * block_size];
             let data_len = data.len();
             // First block
             if let Some(block) = self.cache.find(|item| item.block == start_block) {
-                data[0..512].copy_from_slice(&block.data);
+                data[0..block_size].copy_from_slice(&block.data);
             } else {
-                let original_data = self.read_blocks(start_block, 1);
-                data[0..512].copy_from_slice(&original_data);
+                let original_data = self
+                    .read_blocks(start_block, 1)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                data[0..block_size].copy_from_slice(&original_data);
             }
 
             // Last block
             if let Some(block) = self.cache.find(|item| item.block == end_block) {
-                data[data_len - 512..data_len].copy_from_slice(&block.data);
+                data[data_len - block_size..data_len].copy_from_slice(&block.data);
             } else {
-                let original_data = self.read_blocks(end_block, 1);
-                data[data_len - 512..data_len].copy_from_slice(&original_data);
+                let original_data = self
+                    .read_blocks(end_block, 1)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                data[data_len - block_size..data_len].copy_from_slice(&original_data);
             }
             data[offset_in_start_block..offset_in_start_block + buf.len()].copy_from_slice(buf);
 
-            self.write_blocks(start_block, num_blocks, &data);
+            self.write_blocks(start_block, num_blocks, &data)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
             // Update the cache
             for i in 0..num_blocks {
-                let key = start_block + i as u32;
+                let key = start_block + i as u64;
                 // If the evicted block was dirty, we don't need to write it back because we already wrote it back just above
                 if let Some(item) = self.cache.find(|item| item.block == key) {
                     item.data.copy_from_slice(&data);
                 } else {
-                    let value =
-                        CacheEntry::from_vec(key, &data[i as usize * 512..(i + 1) as usize * 512], false);
+                    let value = CacheEntry::from_vec(
+                        key,
+                        &data[i as usize * block_size..(i + 1) as usize * block_size],
+                        false,
+                    );
                     if let Some(evicted_entry) = self.cache.insert(value) {
                         if evicted_entry.dirty {
-                            self.write_blocks(key, 1, &evicted_entry.data);
+                            if let Err(err) = self.write_blocks(evicted_entry.block, 1, &evicted_entry.data) {
+                                tracing::warn!(block = evicted_entry.block, %err, "failed to flush evicted cache entry");
+                            }
                         }
                     }
                 }
@@ -593,25 +1041,38 @@ impl InquiryResponse {
     }
 }
 
+/// Parsed reply to READ CAPACITY (16). `returned_logical_block_address` is
+/// the address of the *last* logical block (the device has that plus one
+/// blocks in total), reported as a full 64-bit value so devices larger than
+/// 2 TiB don't wrap like they would with READ CAPACITY (10)'s 32-bit field.
 #[derive(Debug)]
 pub struct ReadCapacityResponse {
-    pub returned_logical_block_address: u32,
+    pub returned_logical_block_address: u64,
     pub block_length_in_bytes: u32,
     pub capacity_in_bytes: u64,
+    /// Whether the device is thin provisioned (LBPME bit), i.e. whether it
+    /// will honor UNMAP / WRITE SAME with the UNMAP bit set.
+    pub thin_provisioned: bool,
 }
 
 impl ReadCapacityResponse {
     fn from_bytes(data: &[u8]) -> Self {
         let mut data = Bytes::copy_from_slice(data);
-        let returned_logical_block_address = data.get_u32();
+        let returned_logical_block_address = data.get_u64();
         let block_length_in_bytes = data.get_u32();
         let capacity_in_bytes: u64 =
-            returned_logical_block_address as u64 * block_length_in_bytes as u64;
+            (returned_logical_block_address + 1) * block_length_in_bytes as u64;
+
+        // Bytes 12-13 (P_TYPE/PROT_EN, P_I_EXPONENT/LOGICAL BLOCKS PER
+        // PHYSICAL BLOCK EXPONENT) don't matter here.
+        data.advance(2);
+        let thin_provisioned = (data.get_u8() & 0b1000_0000) != 0; // LBPME
 
         Self {
             block_length_in_bytes,
             returned_logical_block_address,
             capacity_in_bytes,
+            thin_provisioned,
         }
     }
 }
@@ -0,0 +1,197 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use bytes::{Buf, Bytes};
+
+/// A single partition, decoded the same way whether it came from an MBR or
+/// a GPT table, so callers like `ls`/`cat`/`tree`/`write` can pick one by
+/// index without caring which kind of table the disk actually has.
+#[derive(Debug, Clone)]
+pub struct PartitionInfo {
+    pub index: usize,
+    pub starting_lba: u64,
+    pub sector_count: u64,
+    pub sector_size: u64,
+    pub type_name: String,
+    pub label: Option<String>,
+}
+
+impl PartitionInfo {
+    pub fn size_bytes(&self) -> u64 {
+        self.sector_count * self.sector_size
+    }
+
+    /// The `(start, end)` byte range `fscommon::StreamSlice` expects to
+    /// window a device down to just this partition.
+    pub fn byte_range(&self) -> (u64, u64) {
+        let start = self.starting_lba * self.sector_size;
+        (start, start + self.size_bytes())
+    }
+}
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+
+/// GPT's protective-MBR marker: an MBR whose sole partition entry carries
+/// this system ID, telling GPT-aware tools to ignore the MBR table and read
+/// the real partition list from the GPT header at LBA 1 instead.
+const PROTECTIVE_MBR_TYPE: u8 = 0xEE;
+
+/// Largest partition entry array `gpt_partitions` will read. The spec's
+/// usual 128 entries of 128 bytes each is 16 KiB; this gives a lot of
+/// headroom over that while still keeping a malformed or malicious GPT
+/// header (a bogus `num_entries`/`entry_size` pair) from forcing a huge
+/// allocation.
+const MAX_GPT_ENTRIES_BYTES: usize = 1024 * 1024;
+
+fn mbr_partitions(mbr: &mbrman::MBR) -> Vec<PartitionInfo> {
+    let sector_size = mbr.sector_size as u64;
+    mbr.iter()
+        .filter(|(_, partition)| partition.is_used())
+        .map(|(index, partition)| PartitionInfo {
+            // mbrman numbers entries from 1; shift to the 0-based index GPT
+            // entries get, so index 0 is the first entry either way.
+            index: index as usize - 1,
+            starting_lba: partition.starting_lba as u64,
+            sector_count: partition.sectors as u64,
+            sector_size,
+            type_name: format!("{:#04x}", partition.sys),
+            label: None,
+        })
+        .collect()
+}
+
+/// Formats a 16-byte GPT GUID in the usual mixed-endian
+/// `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx` form.
+fn format_guid(bytes: &[u8]) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+        u16::from_le_bytes(bytes[4..6].try_into().unwrap()),
+        u16::from_le_bytes(bytes[6..8].try_into().unwrap()),
+        bytes[8],
+        bytes[9],
+        bytes[10],
+        bytes[11],
+        bytes[12],
+        bytes[13],
+        bytes[14],
+        bytes[15],
+    )
+}
+
+/// A handful of type GUIDs worth naming; anything else is reported as its
+/// raw GUID rather than guessed at.
+fn gpt_type_name(guid: &str) -> String {
+    match guid {
+        "c12a7328-f81f-11d2-ba4b-00a0c93ec93b" => "EFI System".to_owned(),
+        "ebd0a0a2-b9e5-4433-87c0-68b6b72699c7" => "Microsoft basic data".to_owned(),
+        "0fc63daf-8483-4772-8e79-3d69d8477de4" => "Linux filesystem".to_owned(),
+        "0657fd6d-a4ab-43c4-84e5-0933c84b4f4f" => "Linux swap".to_owned(),
+        other => other.to_owned(),
+    }
+}
+
+fn decode_utf16le_name(bytes: &[u8]) -> Option<String> {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect();
+    if units.is_empty() {
+        None
+    } else {
+        Some(String::from_utf16_lossy(&units))
+    }
+}
+
+/// Reads the GPT header at LBA 1 and its partition entry array, returning
+/// every entry whose type GUID isn't all-zero (i.e. actually in use).
+fn gpt_partitions<D: Read + Seek>(
+    device: &mut D,
+    sector_size: u64,
+) -> anyhow::Result<Vec<PartitionInfo>> {
+    device.seek(SeekFrom::Start(sector_size))?;
+    let mut header = vec![0u8; sector_size as usize];
+    device.read_exact(&mut header)?;
+
+    let mut buf = Bytes::copy_from_slice(&header);
+    let mut signature = [0u8; 8];
+    buf.copy_to_slice(&mut signature);
+    anyhow::ensure!(&signature == GPT_SIGNATURE, "not a valid GPT header");
+
+    // revision, header_size, header_crc32, reserved, my_lba, alternate_lba,
+    // first_usable_lba, last_usable_lba, disk_guid
+    buf.advance(4 + 4 + 4 + 4 + 8 + 8 + 8 + 8 + 16);
+    let partition_entries_lba = buf.get_u64_le();
+    let num_entries = buf.get_u32_le() as usize;
+    let entry_size = buf.get_u32_le() as usize;
+
+    // `entry_size` comes straight off the device; 0 would make
+    // `chunks_exact` below panic unconditionally, and anything smaller than
+    // the fields we read out of each entry (the type GUID at [0..16], the
+    // LBA range at [32..56]) would panic on the slice indexing instead.
+    // A corrupted or malicious GPT table can claim either, so both have to
+    // be rejected with an error rather than trusted.
+    anyhow::ensure!(
+        entry_size >= 56,
+        "invalid GPT partition entry size: {entry_size}"
+    );
+    let entries_len = num_entries
+        .checked_mul(entry_size)
+        .filter(|&len| len <= MAX_GPT_ENTRIES_BYTES)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "GPT partition entry array is invalid or too large (num_entries={num_entries}, entry_size={entry_size})"
+            )
+        })?;
+
+    device.seek(SeekFrom::Start(partition_entries_lba * sector_size))?;
+    let mut entries = vec![0u8; entries_len];
+    device.read_exact(&mut entries)?;
+
+    let mut partitions = Vec::new();
+    for (index, entry) in entries.chunks_exact(entry_size).enumerate() {
+        let type_guid = &entry[0..16];
+        if type_guid.iter().all(|&b| b == 0) {
+            continue; // unused entry
+        }
+
+        let mut rest = Bytes::copy_from_slice(&entry[32..56]);
+        let starting_lba = rest.get_u64_le();
+        let ending_lba = rest.get_u64_le();
+        let _attributes = rest.get_u64_le();
+
+        partitions.push(PartitionInfo {
+            index,
+            starting_lba,
+            sector_count: ending_lba + 1 - starting_lba,
+            sector_size,
+            type_name: gpt_type_name(&format_guid(type_guid)),
+            label: decode_utf16le_name(&entry[56..entry_size.min(56 + 72)]),
+        });
+    }
+
+    Ok(partitions)
+}
+
+/// Lists every partition on `device`, preferring a GPT table when the MBR
+/// is a protective one (type `0xEE`), falling back to the MBR's own
+/// partition entries otherwise. `sector_size` must be the device's real
+/// logical block size (512 on most media, but 4096 on 4Kn/Advanced Format
+/// disks) -- both the MBR and GPT tables are laid out in units of it, so
+/// assuming 512 on a 4Kn disk reads every table and byte range at the
+/// wrong offset.
+pub fn list_partitions<D: Read + Seek>(
+    device: &mut D,
+    sector_size: u32,
+) -> anyhow::Result<Vec<PartitionInfo>> {
+    let mbr = mbrman::MBR::read_from(device, sector_size)?;
+    let is_protective = mbr
+        .iter()
+        .any(|(_, partition)| partition.sys == PROTECTIVE_MBR_TYPE);
+
+    if is_protective {
+        gpt_partitions(device, mbr.sector_size as u64)
+    } else {
+        Ok(mbr_partitions(&mbr))
+    }
+}
@@ -0,0 +1,220 @@
+use usb_wasm_bindings::{
+    device::{UsbConfiguration, UsbDevice, UsbEndpoint, UsbInterface},
+    types::{ControlSetup, ControlSetupRecipient, ControlSetupType, Direction, TransferType},
+};
+
+use crate::ftdi::{FtdiDevice, Parity, StopBits};
+
+/// USB-IF interface class for a CDC data interface (the one with the bulk
+/// endpoints; `SET_LINE_CODING`/`SET_CONTROL_LINE_STATE` target it the same
+/// as the data it carries, rather than a separate Communications interface,
+/// to keep this in step with [`FtdiDevice`]'s one-interface model).
+const CDC_DATA_INTERFACE_CLASS: u8 = 0x0A;
+
+const FTDI_VENDOR_ID: u16 = 0x0403;
+
+// CDC-ACM class-specific control requests (USB CDC spec, table 46).
+const CDC_REQUEST_SET_LINE_CODING: u8 = 0x20;
+const CDC_REQUEST_SET_CONTROL_LINE_STATE: u8 = 0x22;
+
+// `SET_CONTROL_LINE_STATE`'s value bitmap (USB CDC spec, table 51).
+const CONTROL_LINE_STATE_DTR: u16 = 1 << 0;
+const CONTROL_LINE_STATE_RTS: u16 = 1 << 1;
+
+/// Line settings shared by both serial device families, handed to
+/// [`open_serial`].
+#[derive(Debug, Clone, Copy)]
+pub struct SerialConfig {
+    pub baud: u32,
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            baud: 9600,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+}
+
+/// Turns a CDC-ACM data interface into a byte stream, the same way
+/// [`FtdiDevice`] does for FTDI's vendor protocol: the caller hands us an
+/// already-opened device and its data-class (`0x0A`) interface, and we issue
+/// the CDC class control requests and speak its bulk endpoints directly.
+/// Unlike FTDI's Bulk-IN packets, CDC-ACM's carry no per-packet status
+/// prefix to strip.
+pub struct CdcAcmDevice {
+    bulk_in: UsbEndpoint,
+    bulk_out: UsbEndpoint,
+    interface_number: u16,
+    _interface: UsbInterface, // We need to keep these alive because of the endpoint resources
+    _configuration: UsbConfiguration, // We need to keep these alive because of the endpoint resources
+    device: UsbDevice,
+}
+
+impl CdcAcmDevice {
+    pub fn new(device: UsbDevice, configuration: UsbConfiguration, interface: UsbInterface) -> Self {
+        device.open();
+        device.reset();
+        if device.active_configuration().descriptor().number != configuration.descriptor().number {
+            device.select_configuration(&configuration);
+        };
+        device.claim_interface(&interface);
+
+        let (bulk_in, bulk_out) = (
+            interface
+                .endpoints()
+                .into_iter()
+                .find(|ep| {
+                    ep.descriptor().direction == Direction::In
+                        && ep.descriptor().transfer_type == TransferType::Bulk
+                })
+                .unwrap(),
+            interface
+                .endpoints()
+                .into_iter()
+                .find(|ep| {
+                    ep.descriptor().direction == Direction::Out
+                        && ep.descriptor().transfer_type == TransferType::Bulk
+                })
+                .unwrap(),
+        );
+        let interface_number = interface.descriptor().interface_number as u16;
+
+        CdcAcmDevice {
+            device,
+            _configuration: configuration,
+            _interface: interface,
+            bulk_in,
+            bulk_out,
+            interface_number,
+        }
+    }
+
+    /// Sets baud, parity, and stop bits via `SET_LINE_CODING`, then asserts
+    /// DTR/RTS via `SET_CONTROL_LINE_STATE` -- many CDC-ACM devices hold off
+    /// sending data until DTR is raised, the same way a real terminal would.
+    pub fn open(&self, baud: u32, data_bits: u8, parity: Parity, stop_bits: StopBits) {
+        let mut line_coding = Vec::with_capacity(7);
+        line_coding.extend_from_slice(&baud.to_le_bytes());
+        line_coding.push(match stop_bits {
+            StopBits::One => 0,
+            StopBits::OnePointFive => 1,
+            StopBits::Two => 2,
+        });
+        line_coding.push(match parity {
+            Parity::None => 0,
+            Parity::Odd => 1,
+            Parity::Even => 2,
+            Parity::Mark => 3,
+            Parity::Space => 4,
+        });
+        line_coding.push(data_bits);
+
+        self.class_control(CDC_REQUEST_SET_LINE_CODING, 0, line_coding);
+        self.class_control(
+            CDC_REQUEST_SET_CONTROL_LINE_STATE,
+            CONTROL_LINE_STATE_DTR | CONTROL_LINE_STATE_RTS,
+            vec![],
+        );
+    }
+
+    pub fn write(&self, data: &[u8]) {
+        self.device.write_bulk(&self.bulk_out, data);
+    }
+
+    /// Reads up to `max` bytes. Unlike [`FtdiDevice::read`], CDC-ACM's
+    /// Bulk-IN packets carry no status prefix, so this is a plain
+    /// passthrough.
+    pub fn read(&self, max: usize) -> Vec<u8> {
+        self.device.read_bulk(&self.bulk_in, max as u64)
+    }
+
+    fn class_control(&self, request: u8, value: u16, data: Vec<u8>) {
+        self.device.write_control(
+            ControlSetup {
+                request_type: ControlSetupType::Class,
+                request_recipient: ControlSetupRecipient::Interface,
+                request,
+                value,
+                index: self.interface_number,
+            },
+            data,
+        );
+    }
+}
+
+enum SerialBackend {
+    Ftdi(FtdiDevice),
+    CdcAcm(CdcAcmDevice),
+}
+
+/// Presents either an FTDI or a CDC-ACM device as a plain byte stream, so a
+/// caller reading SCPI/AT responses line-by-line doesn't need to know or
+/// care which one it's actually talking to, or re-derive either device
+/// family's framing itself. Built by [`open_serial`].
+pub struct SerialPort {
+    backend: SerialBackend,
+}
+
+impl SerialPort {
+    pub fn write(&self, data: &[u8]) {
+        match &self.backend {
+            SerialBackend::Ftdi(device) => device.write(data),
+            SerialBackend::CdcAcm(device) => device.write(data),
+        }
+    }
+
+    pub fn read(&self, max: usize) -> Vec<u8> {
+        match &self.backend {
+            SerialBackend::Ftdi(device) => device.read(max),
+            SerialBackend::CdcAcm(device) => device.read(max),
+        }
+    }
+}
+
+/// Finds the first enumerated FTDI (vendor `0x0403`) or CDC-ACM (data
+/// interface class `0x0A`) device, opens it with `config`, and returns a
+/// ready-to-use [`SerialPort`]. Returns `None` if nothing matching is
+/// plugged in.
+pub fn open_serial(config: SerialConfig) -> Option<SerialPort> {
+    for device in UsbDevice::enumerate() {
+        let is_ftdi = device.descriptor().vendor_id == FTDI_VENDOR_ID;
+        let configuration = device.configurations().remove(0);
+
+        let interface = if is_ftdi {
+            // FTDI chips report a vendor-specific interface class rather
+            // than CDC's 0x0A, so any interface on a 0x0403 device is fair
+            // game -- there's only ever one.
+            configuration.interfaces().into_iter().next()
+        } else {
+            configuration.interfaces().into_iter().find(|interface| {
+                interface.descriptor().interface_class == CDC_DATA_INTERFACE_CLASS
+            })
+        };
+        let Some(interface) = interface else {
+            continue;
+        };
+
+        let port = if is_ftdi {
+            let ftdi = FtdiDevice::new(device, configuration, interface);
+            ftdi.open(config.baud, config.data_bits, config.parity, config.stop_bits);
+            SerialPort {
+                backend: SerialBackend::Ftdi(ftdi),
+            }
+        } else {
+            let cdc_acm = CdcAcmDevice::new(device, configuration, interface);
+            cdc_acm.open(config.baud, config.data_bits, config.parity, config.stop_bits);
+            SerialPort {
+                backend: SerialBackend::CdcAcm(cdc_acm),
+            }
+        };
+        return Some(port);
+    }
+    None
+}
@@ -0,0 +1,343 @@
+use std::thread::sleep;
+use std::time::Duration;
+
+use bytes::{Buf, Bytes, BytesMut, BufMut};
+use thiserror::Error;
+use tracing::trace;
+use usb_wasm_bindings::{
+    device::{UsbConfiguration, UsbDevice, UsbEndpoint, UsbInterface},
+    types::{ControlSetup, ControlSetupRecipient, ControlSetupType, Direction, TransferType},
+};
+
+/// Largest `DEV_DEP_MSG_IN` response we're willing to ask for in one go.
+const MAX_TRANSFER_SIZE: u32 = 4096;
+
+const MSG_ID_DEV_DEP_MSG_OUT: u8 = 1;
+const MSG_ID_REQUEST_DEV_DEP_MSG_IN: u8 = 2;
+const MSG_ID_DEV_DEP_MSG_IN: u8 = 2;
+
+// USBTMC class-specific control requests (USBTMC spec, table 15).
+const CLASS_REQUEST_INITIATE_ABORT_BULK_OUT: u8 = 1;
+const CLASS_REQUEST_CHECK_ABORT_BULK_OUT_STATUS: u8 = 2;
+const CLASS_REQUEST_INITIATE_ABORT_BULK_IN: u8 = 3;
+const CLASS_REQUEST_CHECK_ABORT_BULK_IN_STATUS: u8 = 4;
+const CLASS_REQUEST_INITIATE_CLEAR: u8 = 5;
+const CLASS_REQUEST_CHECK_CLEAR_STATUS: u8 = 6;
+const CLASS_REQUEST_GET_CAPABILITIES: u8 = 7;
+
+// USBTMC_status values (USBTMC spec, table 16).
+const USBTMC_STATUS_SUCCESS: u8 = 0x01;
+const USBTMC_STATUS_PENDING: u8 = 0x02;
+const USBTMC_STATUS_FAILED: u8 = 0x80;
+
+/// How long to wait between `CHECK_*_STATUS` polls while the device reports
+/// `USBTMC_STATUS_PENDING`.
+const STATUS_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Upper bound on `CHECK_*_STATUS` polls before giving up on a device that
+/// never stops reporting `USBTMC_STATUS_PENDING`.
+const STATUS_POLL_ATTEMPTS: u32 = 100;
+
+#[derive(Debug, Error)]
+pub enum UsbTmcError {
+    #[error("The device responded with a different bTag than was expected")]
+    IncorrectTag,
+    #[error("The device reported USBTMC status 0x{0:02x}")]
+    RequestFailed(u8),
+    #[error("The device never left USBTMC_STATUS_PENDING")]
+    StillPending,
+    #[error("The device's DEV_DEP_MSG_IN response was too short or had an unexpected MsgID")]
+    MalformedResponse,
+}
+
+/// USB488 talk/listen/trigger capability bits parsed out of the 24-byte
+/// `GET_CAPABILITIES` block (USBTMC-USB488 spec, table 8).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UsbTmcCapabilities {
+    pub is_talker: bool,
+    pub is_listener: bool,
+    pub supports_trigger: bool,
+    pub supports_indicator_pulse: bool,
+}
+
+/// Alias matching the "instrument session" name other USBTMC-aware guests
+/// reach for -- this is the same type as [`UsbTmcDevice`], just under the
+/// name a SCPI-instrument-focused caller is more likely to look for.
+pub type UsbtmcSession = UsbTmcDevice;
+
+// Implementation of the USBTMC/USB488 transport on top of the same bulk
+// in/out endpoints BulkOnlyTransportDevice uses, mirroring its CBW/CSW tag
+// handling with USBTMC's bTag instead.
+pub struct UsbTmcDevice {
+    bulk_in: UsbEndpoint,
+    bulk_out: UsbEndpoint,
+    _interface: UsbInterface, // We need to keep these alive because of the endpoint resources
+    _configuration: UsbConfiguration, // We need to keep these alive because of the endpoint resources
+    device: UsbDevice,
+    current_tag: u8,
+}
+
+impl UsbTmcDevice {
+    // Also opens the device, selects the configuration, and claims the interface
+    pub fn new(
+        device: UsbDevice,
+        configuration: UsbConfiguration,
+        interface: UsbInterface,
+    ) -> Self {
+        device.open();
+        device.reset();
+        if device.active_configuration().descriptor().number != configuration.descriptor().number {
+            device.select_configuration(&configuration);
+        };
+        device.claim_interface(&interface);
+
+        // Find endpoints
+        let (bulk_in, bulk_out) = {
+            (
+                interface
+                    .endpoints()
+                    .into_iter()
+                    .find(|ep| {
+                        ep.descriptor().direction == Direction::In
+                            && ep.descriptor().transfer_type == TransferType::Bulk
+                    })
+                    .unwrap(),
+                interface
+                    .endpoints()
+                    .into_iter()
+                    .find(|ep| {
+                        ep.descriptor().direction == Direction::Out
+                            && ep.descriptor().transfer_type == TransferType::Bulk
+                    })
+                    .unwrap(),
+            )
+        };
+
+        UsbTmcDevice {
+            device,
+            _configuration: configuration,
+            _interface: interface,
+
+            bulk_in,
+            bulk_out,
+
+            current_tag: 0,
+        }
+    }
+
+    /// Sends `message` as a single `DEV_DEP_MSG_OUT` (SCPI command, no
+    /// response expected).
+    pub fn write(&mut self, message: &str) -> Result<(), UsbTmcError> {
+        self.send_dev_dep_msg_out(message.as_bytes())
+    }
+
+    /// Issues a `REQUEST_DEV_DEP_MSG_IN` and returns the decoded
+    /// `DEV_DEP_MSG_IN` response payload, without sending anything first.
+    /// Use this to read a response to a query the instrument already has
+    /// pending (e.g. after a `*TRG` over the interrupt-IN endpoint).
+    pub fn read(&mut self) -> Result<String, UsbTmcError> {
+        let tag = self.next_tag();
+        let mut header = BytesMut::with_capacity(12);
+        header.put_u8(MSG_ID_REQUEST_DEV_DEP_MSG_IN);
+        header.put_u8(tag);
+        header.put_u8(tag ^ 0xFF);
+        header.put_u8(0); // reserved
+        header.put_u32_le(MAX_TRANSFER_SIZE);
+        header.put_u8(0); // bmTransferAttributes: no term char
+        header.put_bytes(0, 3); // reserved
+
+        trace!(tag, "Sending REQUEST_DEV_DEP_MSG_IN");
+        self.device.write_bulk(&self.bulk_out, &header);
+
+        let response = self
+            .device
+            .read_bulk(&self.bulk_in, 12 + MAX_TRANSFER_SIZE as u64);
+        let mut response = Bytes::from(response);
+
+        // A real instrument can send back a short or garbled reply (a
+        // dropped packet, a device that doesn't speak USBTMC properly,
+        // ...); the 12-byte DEV_DEP_MSG_IN header has to be there in full
+        // before we trust any of its fields, or `Buf`'s `get_*` calls below
+        // would panic on a short buffer instead of reporting it.
+        if response.len() < 12 {
+            return Err(UsbTmcError::MalformedResponse);
+        }
+
+        let msg_id = response.get_u8();
+        let returned_tag = response.get_u8();
+        let _inverted_tag = response.get_u8();
+        let _reserved = response.get_u8();
+        if msg_id != MSG_ID_DEV_DEP_MSG_IN {
+            return Err(UsbTmcError::MalformedResponse);
+        }
+        if returned_tag != tag {
+            return Err(UsbTmcError::IncorrectTag);
+        }
+
+        let transfer_size = response.get_u32_le();
+        let _bm_transfer_attributes = response.get_u8();
+        response.advance(3); // reserved
+
+        let payload = response.split_to((transfer_size as usize).min(response.len()));
+        trace!(tag, len = payload.len(), "Received DEV_DEP_MSG_IN");
+        Ok(String::from_utf8_lossy(&payload).into_owned())
+    }
+
+    /// Sends `message` as a `DEV_DEP_MSG_OUT`, then issues a
+    /// `REQUEST_DEV_DEP_MSG_IN` and returns the decoded response payload.
+    pub fn query(&mut self, message: &str) -> Result<String, UsbTmcError> {
+        self.send_dev_dep_msg_out(message.as_bytes())?;
+        self.read()
+    }
+
+    /// Requests the device abort whatever bulk-OUT transfer is tagged
+    /// `self.current_tag`, then polls `CHECK_ABORT_BULK_OUT_STATUS` until
+    /// it's no longer `USBTMC_STATUS_PENDING`.
+    pub fn initiate_abort_bulk_out(&mut self) -> Result<(), UsbTmcError> {
+        self.initiate_abort(
+            CLASS_REQUEST_INITIATE_ABORT_BULK_OUT,
+            CLASS_REQUEST_CHECK_ABORT_BULK_OUT_STATUS,
+        )
+    }
+
+    /// Requests the device abort whatever bulk-IN transfer is tagged
+    /// `self.current_tag`, then polls `CHECK_ABORT_BULK_IN_STATUS` until
+    /// it's no longer `USBTMC_STATUS_PENDING`.
+    pub fn initiate_abort_bulk_in(&mut self) -> Result<(), UsbTmcError> {
+        self.initiate_abort(
+            CLASS_REQUEST_INITIATE_ABORT_BULK_IN,
+            CLASS_REQUEST_CHECK_ABORT_BULK_IN_STATUS,
+        )
+    }
+
+    fn initiate_abort(
+        &mut self,
+        initiate_request: u8,
+        check_request: u8,
+    ) -> Result<(), UsbTmcError> {
+        let response = self.control_request_in(initiate_request, self.current_tag as u16, 2);
+        self.require_success(response[0])?;
+
+        self.poll_until_not_pending(|device| {
+            device.control_request_in(check_request, 0, 1)[0]
+        })
+    }
+
+    /// Sends `INITIATE_CLEAR` to reset the device's bulk-IN/bulk-OUT state
+    /// (e.g. after an error leaves it expecting a `DEV_DEP_MSG_IN` the guest
+    /// never asked for), then polls `CHECK_CLEAR_STATUS` until it settles.
+    pub fn initiate_clear(&mut self) -> Result<(), UsbTmcError> {
+        let response = self.control_request_in(CLASS_REQUEST_INITIATE_CLEAR, 0, 1);
+        self.require_success(response[0])?;
+
+        self.poll_until_not_pending(|device| {
+            device.control_request_in(CLASS_REQUEST_CHECK_CLEAR_STATUS, 0, 2)[0]
+        })
+    }
+
+    /// Recovers a session that's gotten out of sync with the instrument --
+    /// e.g. a `query()` whose response never arrived, leaving the device
+    /// mid-transfer on both bulk endpoints. Aborts whatever's in flight on
+    /// bulk-IN and bulk-OUT, then clears the device's USBTMC state, so the
+    /// next `write`/`query` starts from a known-good point.
+    pub fn recover(&mut self) -> Result<(), UsbTmcError> {
+        self.initiate_abort_bulk_out()?;
+        self.initiate_abort_bulk_in()?;
+        self.initiate_clear()
+    }
+
+    /// Polls `check` until it stops reporting `USBTMC_STATUS_PENDING`,
+    /// sleeping [`STATUS_POLL_INTERVAL`] between attempts, giving up after
+    /// [`STATUS_POLL_ATTEMPTS`].
+    fn poll_until_not_pending(
+        &mut self,
+        mut check: impl FnMut(&mut Self) -> u8,
+    ) -> Result<(), UsbTmcError> {
+        for _ in 0..STATUS_POLL_ATTEMPTS {
+            let status = check(self);
+            if status != USBTMC_STATUS_PENDING {
+                return self.require_success(status);
+            }
+            sleep(STATUS_POLL_INTERVAL);
+        }
+        Err(UsbTmcError::StillPending)
+    }
+
+    fn require_success(&self, status: u8) -> Result<(), UsbTmcError> {
+        if status == USBTMC_STATUS_SUCCESS {
+            return Ok(());
+        }
+        if status == USBTMC_STATUS_FAILED {
+            trace!("USBTMC request failed");
+        }
+        Err(UsbTmcError::RequestFailed(status))
+    }
+
+    /// Issues a USBTMC class-specific, `Recipient::Interface` control-IN
+    /// request and returns the raw response.
+    fn control_request_in(&self, request: u8, value: u16, length: u64) -> Vec<u8> {
+        self.device.read_control(
+            ControlSetup {
+                request_type: ControlSetupType::Class,
+                request_recipient: ControlSetupRecipient::Interface,
+                request,
+                value,
+                index: self._interface.descriptor().interface_number as u16,
+            },
+            length,
+        )
+    }
+
+    /// Reads the device's 24-byte `GET_CAPABILITIES` block (class control-IN,
+    /// `bRequest = 7`) and parses out the USB488 talk/listen/trigger bits.
+    pub fn get_capabilities(&self) -> UsbTmcCapabilities {
+        let data = self.control_request_in(CLASS_REQUEST_GET_CAPABILITIES, 0, 24);
+
+        // USBTMC-USB488 spec, table 8: the USB488 interface/device
+        // capability bytes are bytes 14 and 15 of the 24-byte block.
+        let usb488_interface_capabilities = data[14];
+        let usb488_device_capabilities = data[15];
+
+        UsbTmcCapabilities {
+            is_listener: usb488_interface_capabilities & 0b0000_0001 != 0,
+            is_talker: usb488_interface_capabilities & 0b0000_0010 != 0,
+            supports_trigger: usb488_interface_capabilities & 0b0000_0100 != 0,
+            supports_indicator_pulse: usb488_device_capabilities & 0b0000_0100 != 0,
+        }
+    }
+
+    fn send_dev_dep_msg_out(&mut self, data: &[u8]) -> Result<(), UsbTmcError> {
+        let tag = self.next_tag();
+
+        let mut message = BytesMut::with_capacity(12 + data.len());
+        message.put_u8(MSG_ID_DEV_DEP_MSG_OUT);
+        message.put_u8(tag);
+        message.put_u8(tag ^ 0xFF);
+        message.put_u8(0); // reserved
+        message.put_u32_le(data.len() as u32);
+        message.put_u8(0b0000_0001); // bmTransferAttributes: EOM
+        message.put_bytes(0, 3); // reserved
+        message.put_slice(data);
+        pad_to_4_byte_boundary(&mut message);
+
+        trace!(tag, len = data.len(), "Sending DEV_DEP_MSG_OUT");
+        self.device.write_bulk(&self.bulk_out, &message);
+        Ok(())
+    }
+
+    // bTag increments 1..255 and is never 0; `read` compares the bTag the
+    // device echoes in DEV_DEP_MSG_IN against it to catch a stale response.
+    fn next_tag(&mut self) -> u8 {
+        self.current_tag = if self.current_tag >= 255 {
+            1
+        } else {
+            self.current_tag + 1
+        };
+        self.current_tag
+    }
+}
+
+fn pad_to_4_byte_boundary(message: &mut BytesMut) {
+    let padding = (4 - message.len() % 4) % 4;
+    message.put_bytes(0, padding);
+}
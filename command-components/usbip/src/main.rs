@@ -0,0 +1,255 @@
+mod protocol;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use anyhow::anyhow;
+use bytes::{Buf, Bytes};
+use clap::Parser;
+use tracing::{info, warn, Level};
+use usb_wasm_bindings::{
+    device::{UsbDevice, UsbEndpoint},
+    types::{ControlSetup, ControlSetupRecipient, ControlSetupType, Direction, TransferType},
+};
+
+use protocol::{
+    busid_for_index, decode_command, encode_devlist_reply, encode_import_reply,
+    encode_submit_reply, encode_unlink_reply, Command, UsbIpError, OP_REQ_DEVLIST, OP_REQ_IMPORT,
+};
+
+const DIRECTION_OUT: u32 = 0;
+const DIRECTION_IN: u32 = 1;
+
+/// Linux's `ENOENT`, used verbatim in error replies the way the real kernel
+/// driver does.
+const ENOENT: i32 = 2;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// TCP port to listen on
+    #[arg(long, default_value_t = 3240)]
+    port: u16,
+}
+
+pub fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
+
+    let args = Args::parse();
+
+    let devices = UsbDevice::enumerate();
+    if devices.is_empty() {
+        return Err(anyhow!("No USB devices found to export"));
+    }
+    for device in &devices {
+        device.open();
+    }
+
+    let listener = TcpListener::bind(("0.0.0.0", args.port))?;
+    info!(
+        port = args.port,
+        device_count = devices.len(),
+        "usbip server listening"
+    );
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        info!(peer = ?stream.peer_addr(), "client connected");
+        if let Err(err) = handle_connection(stream, &devices) {
+            warn!(%err, "usbip connection ended");
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads exactly `buf.len()` bytes, returning `Ok(false)` if the peer closed
+/// the connection cleanly before sending anything (as opposed to mid-message,
+/// which is a `ConnectionClosed` error).
+fn read_exact_or_eof(stream: &mut TcpStream, buf: &mut [u8]) -> Result<bool, UsbIpError> {
+    let mut total = 0;
+    while total < buf.len() {
+        let n = stream.read(&mut buf[total..])?;
+        if n == 0 {
+            return if total == 0 {
+                Ok(false)
+            } else {
+                Err(UsbIpError::ConnectionClosed)
+            };
+        }
+        total += n;
+    }
+    Ok(true)
+}
+
+/// Services one client end-to-end: the `OP_REQ_DEVLIST`/`OP_REQ_IMPORT`
+/// handshake against the full `devices` registry, then, once a busid has
+/// been imported, `USBIP_CMD_SUBMIT`/`USBIP_CMD_UNLINK` packets against that
+/// one device for as long as the connection stays open.
+fn handle_connection(mut stream: TcpStream, devices: &[UsbDevice]) -> Result<(), UsbIpError> {
+    let mut imported: Option<&UsbDevice> = None;
+
+    loop {
+        let Some(device) = imported else {
+            let mut header = [0u8; 8];
+            if !read_exact_or_eof(&mut stream, &mut header)? {
+                return Ok(());
+            }
+            let mut buf = Bytes::copy_from_slice(&header);
+            let _version = buf.get_u16();
+            let command = buf.get_u16();
+            let _status = buf.get_u32();
+
+            match command {
+                OP_REQ_DEVLIST => {
+                    let reply = encode_devlist_reply(
+                        devices.iter().enumerate().map(|(i, d)| (d, busid_for_index(i))),
+                    );
+                    stream.write_all(&reply)?;
+                }
+                OP_REQ_IMPORT => {
+                    let mut busid_bytes = [0u8; 32];
+                    if !read_exact_or_eof(&mut stream, &mut busid_bytes)? {
+                        return Ok(());
+                    }
+                    let busid = String::from_utf8_lossy(&busid_bytes)
+                        .trim_end_matches('\0')
+                        .to_owned();
+
+                    let matched = devices
+                        .iter()
+                        .enumerate()
+                        .find(|(i, _)| busid_for_index(*i) == busid);
+                    stream.write_all(&encode_import_reply(
+                        matched.map(|(_, d)| (d, busid.as_str())),
+                    ))?;
+                    imported = matched.map(|(_, d)| d);
+                }
+                other => {
+                    warn!(command = other, "unexpected opcode before import, closing");
+                    return Ok(());
+                }
+            }
+            continue;
+        };
+
+        let mut header = [0u8; 48];
+        if !read_exact_or_eof(&mut stream, &mut header)? {
+            return Ok(());
+        }
+
+        match decode_command(Bytes::copy_from_slice(&header)) {
+            Command::Submit(submit) => {
+                let out_data = if submit.header.direction == DIRECTION_OUT {
+                    let mut data = vec![0u8; submit.transfer_buffer_length as usize];
+                    if !read_exact_or_eof(&mut stream, &mut data)? {
+                        return Ok(());
+                    }
+                    data
+                } else {
+                    Vec::new()
+                };
+
+                let (status, data) = dispatch_submit(device, &submit, &out_data);
+                stream.write_all(&encode_submit_reply(submit.header.seqnum, status, &data))?;
+            }
+            Command::Unlink(unlink) => {
+                // Every SUBMIT above already runs to completion (the guest
+                // bindings are blocking) before its reply goes out, so by
+                // the time an UNLINK for it could arrive there is nothing
+                // left in flight to cancel — the honest answer is always
+                // "already gone".
+                let _ = unlink.unlink_seqnum;
+                stream.write_all(&encode_unlink_reply(unlink.header.seqnum, -ENOENT))?;
+            }
+        }
+    }
+}
+
+/// Finds the claimed interface's endpoint matching `ep_number`/`direction`,
+/// so a `USBIP_CMD_SUBMIT` on a non-zero endpoint can be routed to the right
+/// bulk or interrupt transfer.
+fn find_endpoint(device: &UsbDevice, ep_number: u32, direction: u32) -> Option<UsbEndpoint> {
+    let want_direction = if direction == DIRECTION_IN {
+        Direction::In
+    } else {
+        Direction::Out
+    };
+
+    device
+        .active_configuration()
+        .interfaces()
+        .into_iter()
+        .find_map(|interface| {
+            interface.endpoints().into_iter().find(|endpoint| {
+                let descriptor = endpoint.descriptor();
+                descriptor.endpoint_number == ep_number as u8
+                    && descriptor.direction == want_direction
+            })
+        })
+}
+
+/// Routes a `USBIP_CMD_SUBMIT` to the matching guest transfer call: endpoint
+/// 0 is always a control transfer decoded from the embedded 8-byte setup
+/// packet, everything else goes to whichever bulk or interrupt endpoint it
+/// names. Returns the `(status, data)` pair `USBIP_RET_SUBMIT` wants, with
+/// `status` being 0 on success or a negative Linux errno.
+fn dispatch_submit(
+    device: &UsbDevice,
+    submit: &protocol::CmdSubmit,
+    out_data: &[u8],
+) -> (i32, Vec<u8>) {
+    let direction = submit.header.direction;
+
+    if submit.header.ep == 0 {
+        let mut setup = Bytes::copy_from_slice(&submit.setup);
+        let request_type_byte = setup.get_u8();
+        let request = setup.get_u8();
+        let value = setup.get_u16_le();
+        let index = setup.get_u16_le();
+        let length = setup.get_u16_le();
+
+        let control_setup = ControlSetup {
+            request_type: match (request_type_byte >> 5) & 0b11 {
+                1 => ControlSetupType::Class,
+                2 => ControlSetupType::Vendor,
+                _ => ControlSetupType::Standard,
+            },
+            request_recipient: match request_type_byte & 0b1_1111 {
+                1 => ControlSetupRecipient::Interface,
+                2 => ControlSetupRecipient::Endpoint,
+                _ => ControlSetupRecipient::Device,
+            },
+            request,
+            value,
+            index,
+        };
+
+        if direction == DIRECTION_IN {
+            (0, device.read_control(control_setup, length))
+        } else {
+            device.write_control(control_setup, out_data.to_vec());
+            (0, Vec::new())
+        }
+    } else if let Some(endpoint) = find_endpoint(device, submit.header.ep, direction) {
+        match endpoint.descriptor().transfer_type {
+            TransferType::Bulk if direction == DIRECTION_IN => (
+                0,
+                device.read_bulk(&endpoint, submit.transfer_buffer_length as u64),
+            ),
+            TransferType::Bulk => {
+                device.write_bulk(&endpoint, out_data);
+                (0, Vec::new())
+            }
+            TransferType::Interrupt if direction == DIRECTION_IN => (
+                0,
+                device.read_interrupt(&endpoint, submit.transfer_buffer_length as u64),
+            ),
+            // Interrupt OUT and isochronous transfers have no guest binding
+            // to dispatch to yet.
+            _ => (-ENOENT, Vec::new()),
+        }
+    } else {
+        (-ENOENT, Vec::new())
+    }
+}
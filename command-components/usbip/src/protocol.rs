@@ -0,0 +1,217 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use thiserror::Error;
+use usb_wasm_bindings::device::UsbDevice;
+
+pub const USBIP_VERSION: u16 = 0x0111;
+
+pub const OP_REQ_DEVLIST: u16 = 0x8005;
+pub const OP_REP_DEVLIST: u16 = 0x0005;
+pub const OP_REQ_IMPORT: u16 = 0x8003;
+pub const OP_REP_IMPORT: u16 = 0x0003;
+
+pub const USBIP_CMD_SUBMIT: u32 = 0x0001;
+pub const USBIP_RET_SUBMIT: u32 = 0x0003;
+pub const USBIP_CMD_UNLINK: u32 = 0x0002;
+pub const USBIP_RET_UNLINK: u32 = 0x0004;
+
+/// `usbip_device_speed::USB_SPEED_HIGH`, the only speed we ever claim since
+/// the WIT interface doesn't expose the negotiated link speed.
+const USB_SPEED_HIGH: u32 = 3;
+
+/// Fixed-size fields the real protocol null-pads rather than length-prefixes.
+const SYSFS_PATH_SIZE: usize = 256;
+const BUS_ID_SIZE: usize = 32;
+
+#[derive(Debug, Error)]
+pub enum UsbIpError {
+    #[error("the client disconnected mid-message")]
+    ConnectionClosed,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Every device this server exports lives on our one synthetic bus "1", so
+/// a device's busid is just its 1-based position in `UsbDevice::enumerate()`.
+pub fn busid_for_index(index: usize) -> String {
+    format!("1-{}", index + 1)
+}
+
+fn put_fixed_str(buf: &mut BytesMut, s: &str, size: usize) {
+    let bytes = s.as_bytes();
+    assert!(bytes.len() < size, "{s} does not fit in {size} bytes");
+    buf.put_slice(bytes);
+    buf.put_bytes(0, size - bytes.len());
+}
+
+/// BCD-packs a `(major, minor, sub)` version tuple the way `bcdDevice` and
+/// `bcdUSB` expect: one nibble per digit.
+fn bcd(version: (u8, u8, u8)) -> u16 {
+    ((version.0 as u16) << 8) | ((version.1 as u16) << 4) | version.2 as u16
+}
+
+/// Encodes the `usbip_usb_device` struct describing `device`: everything
+/// `OP_REP_DEVLIST` and `OP_REP_IMPORT` carry about an exported device
+/// itself, not including (for DEVLIST) the per-interface block that follows.
+fn put_device_info(buf: &mut BytesMut, device: &UsbDevice, busid: &str) {
+    let descriptor = device.descriptor();
+    let configuration = device.active_configuration();
+    let interfaces = configuration.interfaces();
+
+    put_fixed_str(buf, &format!("/sys/devices/usbip/{busid}"), SYSFS_PATH_SIZE);
+    put_fixed_str(buf, busid, BUS_ID_SIZE);
+
+    buf.put_u32(1); // busnum, every device lives on our one synthetic bus
+    buf.put_u32(busid.rsplit('-').next().and_then(|n| n.parse().ok()).unwrap_or(0)); // devnum
+    buf.put_u32(USB_SPEED_HIGH);
+
+    buf.put_u16(descriptor.vendor_id);
+    buf.put_u16(descriptor.product_id);
+    buf.put_u16(bcd(descriptor.device_version));
+
+    buf.put_u8(descriptor.device_class);
+    buf.put_u8(descriptor.device_subclass);
+    buf.put_u8(descriptor.device_protocol);
+    buf.put_u8(configuration.descriptor().number);
+    buf.put_u8(device.configurations().len() as u8);
+    buf.put_u8(interfaces.len() as u8);
+}
+
+/// Builds an `OP_REP_DEVLIST` reply advertising every enumerated device,
+/// paired with its assigned busid, and each one's interfaces.
+pub fn encode_devlist_reply<'a>(devices: impl Iterator<Item = (&'a UsbDevice, String)>) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u16(USBIP_VERSION);
+    buf.put_u16(OP_REP_DEVLIST);
+    buf.put_u32(0); // status: ST_OK
+
+    let devices: Vec<_> = devices.collect();
+    buf.put_u32(devices.len() as u32);
+
+    for (device, busid) in devices {
+        put_device_info(&mut buf, device, &busid);
+        for interface in device.active_configuration().interfaces() {
+            let descriptor = interface.descriptor();
+            buf.put_u8(descriptor.interface_class);
+            buf.put_u8(descriptor.interface_subclass);
+            buf.put_u8(descriptor.interface_protocol);
+            buf.put_u8(0); // padding, for alignment
+        }
+    }
+
+    buf.freeze()
+}
+
+/// Builds an `OP_REP_IMPORT` reply: the device info block on success, or
+/// just the header with a non-zero status if no device matched the
+/// requested busid.
+pub fn encode_import_reply(matched: Option<(&UsbDevice, &str)>) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u16(USBIP_VERSION);
+    buf.put_u16(OP_REP_IMPORT);
+
+    let Some((device, busid)) = matched else {
+        buf.put_u32(1); // status: error
+        return buf.freeze();
+    };
+
+    buf.put_u32(0); // status: ST_OK
+    put_device_info(&mut buf, device, busid);
+    buf.freeze()
+}
+
+/// The fixed 48-byte header every `USBIP_CMD_SUBMIT`/`USBIP_CMD_UNLINK`
+/// packet starts with.
+#[derive(Debug)]
+pub struct UsbIpHeaderCommon {
+    pub command: u32,
+    pub seqnum: u32,
+    pub devid: u32,
+    pub direction: u32,
+    pub ep: u32,
+}
+
+#[derive(Debug)]
+pub struct CmdSubmit {
+    pub header: UsbIpHeaderCommon,
+    pub transfer_buffer_length: u32,
+    pub setup: [u8; 8],
+}
+
+#[derive(Debug)]
+pub struct CmdUnlink {
+    pub header: UsbIpHeaderCommon,
+    pub unlink_seqnum: u32,
+}
+
+pub enum Command {
+    Submit(CmdSubmit),
+    Unlink(CmdUnlink),
+}
+
+/// Parses a 48-byte `USBIP_CMD_SUBMIT`/`USBIP_CMD_UNLINK` basic header. Both
+/// commands share the same on-the-wire size, with the unused tail of
+/// whichever one it isn't left as reserved padding.
+pub fn decode_command(mut bytes: Bytes) -> Command {
+    let command = bytes.get_u32();
+    let header = UsbIpHeaderCommon {
+        command,
+        seqnum: bytes.get_u32(),
+        devid: bytes.get_u32(),
+        direction: bytes.get_u32(),
+        ep: bytes.get_u32(),
+    };
+
+    if command == USBIP_CMD_UNLINK {
+        let unlink_seqnum = bytes.get_u32();
+        Command::Unlink(CmdUnlink {
+            header,
+            unlink_seqnum,
+        })
+    } else {
+        let _transfer_flags = bytes.get_u32();
+        let transfer_buffer_length = bytes.get_u32();
+        bytes.advance(4 + 4 + 4); // start_frame, number_of_packets, interval
+        let mut setup = [0u8; 8];
+        bytes.copy_to_slice(&mut setup);
+        Command::Submit(CmdSubmit {
+            header,
+            transfer_buffer_length,
+            setup,
+        })
+    }
+}
+
+/// Builds a `USBIP_RET_SUBMIT` reply. `data` is the payload returned by an
+/// IN transfer (empty for OUT); `status` is 0 on success or a negative
+/// Linux errno.
+pub fn encode_submit_reply(seqnum: u32, status: i32, data: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(48 + data.len());
+    buf.put_u32(USBIP_RET_SUBMIT);
+    buf.put_u32(seqnum);
+    buf.put_u32(0); // devid, unused in replies
+    buf.put_u32(0); // direction, unused in replies
+    buf.put_u32(0); // ep, unused in replies
+    buf.put_i32(status);
+    buf.put_u32(data.len() as u32); // actual_length
+    buf.put_u32(0); // start_frame
+    buf.put_u32(0); // number_of_packets
+    buf.put_i32(0); // error_count
+    buf.put_u64(0); // setup, unused in replies
+    buf.put_slice(data);
+    buf.freeze()
+}
+
+/// Builds a `USBIP_RET_UNLINK` reply. `status` is 0 if the transfer was
+/// still pending and got cancelled, or a negative errno (e.g. `-ENOENT`) if
+/// it had already completed or never existed.
+pub fn encode_unlink_reply(seqnum: u32, status: i32) -> Bytes {
+    let mut buf = BytesMut::with_capacity(48);
+    buf.put_u32(USBIP_RET_UNLINK);
+    buf.put_u32(seqnum);
+    buf.put_u32(0); // devid, unused in replies
+    buf.put_u32(0); // direction, unused in replies
+    buf.put_u32(0); // ep, unused in replies
+    buf.put_i32(status);
+    buf.put_bytes(0, 24); // reserved, padded to the common 48-byte size
+    buf.freeze()
+}
@@ -1,4 +1,6 @@
-use usb_wasm_bindings::device::UsbDevice;
+mod rumble;
+
+use usb_wasm_bindings::device::{UsbDevice, UsbEndpoint};
 use usb_wasm_bindings::types::Filter;
 
 use std::io;
@@ -153,6 +155,16 @@ fn print_maze(maze: &[[&str; 30]; 14]) {
     }
 }
 
+/// Gives haptic feedback for what Pacman just moved onto: a light pulse for
+/// food, a stronger one for running into a ghost.
+fn rumble_on_move(device: &UsbDevice, endpoint_out: &UsbEndpoint, entered: &str) {
+    if entered == GHOST {
+        rumble::set_rumble(device, endpoint_out, 0xFF, 0xFF);
+    } else if entered == FOOD {
+        rumble::set_rumble(device, endpoint_out, 0x00, 0x40);
+    }
+}
+
 pub fn main() -> anyhow::Result<()> {
     let xbox_controller = UsbDevice::request_device(&Filter {
         vendor_id: Some(0x045e),
@@ -294,40 +306,48 @@ pub fn main() -> anyhow::Result<()> {
                 if state.right {
                     button_down = true;
 
-                    if maze[current_pos.0][current_pos.1 + 1] != WALL {
+                    let target = maze[current_pos.0][current_pos.1 + 1];
+                    if target != WALL {
                         maze[current_pos.0][current_pos.1] = EMPTY;
                         current_pos.1 += 1;
                         maze[current_pos.0][current_pos.1] = PACMAN;
+                        rumble_on_move(&xbox_controller, &endpoint_out, target);
                     }
                 }
 
                 if state.left {
                     button_down = true;
 
-                    if maze[current_pos.0][current_pos.1 - 1] != WALL {
+                    let target = maze[current_pos.0][current_pos.1 - 1];
+                    if target != WALL {
                         maze[current_pos.0][current_pos.1] = EMPTY;
                         current_pos.1 -= 1;
                         maze[current_pos.0][current_pos.1] = PACMAN;
+                        rumble_on_move(&xbox_controller, &endpoint_out, target);
                     }
                 }
 
                 if state.up {
                     button_down = true;
 
-                    if maze[current_pos.0 - 1][current_pos.1] != WALL {
+                    let target = maze[current_pos.0 - 1][current_pos.1];
+                    if target != WALL {
                         maze[current_pos.0][current_pos.1] = EMPTY;
                         current_pos.0 -= 1;
                         maze[current_pos.0][current_pos.1] = PACMAN;
+                        rumble_on_move(&xbox_controller, &endpoint_out, target);
                     }
                 }
 
                 if state.down {
                     button_down = true;
 
-                    if maze[current_pos.0 + 1][current_pos.1] != WALL {
+                    let target = maze[current_pos.0 + 1][current_pos.1];
+                    if target != WALL {
                         maze[current_pos.0][current_pos.1] = EMPTY;
                         current_pos.0 += 1;
                         maze[current_pos.0][current_pos.1] = PACMAN;
+                        rumble_on_move(&xbox_controller, &endpoint_out, target);
                     }
                 }
             } else if !state.up && !state.down && !state.left && !state.right {
@@ -0,0 +1,14 @@
+use usb_wasm_bindings::device::{UsbDevice, UsbEndpoint};
+
+/// Sends the Xbox One controller's rumble output report on its interrupt
+/// OUT endpoint, per https://github.com/quantus/xbox-one-controller-protocol
+/// (the same reference this demo already cites for its initial setup
+/// report): a fixed 15-byte packet starting `0x09 0x00`, with the two motor
+/// magnitudes in bytes 10/11.
+pub fn set_rumble(device: &UsbDevice, endpoint_out: &UsbEndpoint, strong: u8, weak: u8) {
+    let report = [
+        0x09, 0x00, 0x00, 0x09, 0x00, 0x0F, 0x00, 0x00, 0x00, 0x00, strong, weak, 0xFF, 0x00,
+        0x00,
+    ];
+    device.write_interrupt(endpoint_out, &report);
+}
@@ -0,0 +1,237 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use futures_core::Stream;
+use thiserror::Error;
+use usb_wasm_bindings::device::{UsbDevice, UsbEndpoint};
+use usb_wasm_bindings::types::ControlSetup;
+
+/// Errors an async transfer can observe mid-flight that a blocking call
+/// would otherwise just hang or panic on: a stalled endpoint, a transfer
+/// that timed out, or the device having gone away entirely.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum TransferError {
+    #[error("endpoint stalled")]
+    Stall,
+    #[error("transfer timed out")]
+    Timeout,
+    #[error("device disconnected")]
+    Disconnected,
+}
+
+// NOTE: the underlying usb-wasm-bindings guest calls are still synchronous
+// blocking host calls — the WIT interface doesn't yet expose a way for the
+// host to signal transfer completion asynchronously. So every future below
+// resolves on its first `poll`, the same way embassy-usb's endpoint driver
+// would if the hardware interrupt it waits on always fired immediately.
+// Once the host interface grows a real completion signal, only these
+// `poll` bodies need to change to register the waker and return
+// `Poll::Pending` until that signal arrives.
+
+pub struct InterruptTransfer<'a> {
+    device: &'a UsbDevice,
+    endpoint: &'a UsbEndpoint,
+    length: u64,
+}
+
+impl Future for InterruptTransfer<'_> {
+    type Output = Result<Vec<u8>, TransferError>;
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Poll::Ready(Ok(self.device.read_interrupt(self.endpoint, self.length)))
+    }
+}
+
+pub struct BulkReadTransfer<'a> {
+    device: &'a UsbDevice,
+    endpoint: &'a UsbEndpoint,
+    length: u64,
+}
+
+impl Future for BulkReadTransfer<'_> {
+    type Output = Result<Vec<u8>, TransferError>;
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Poll::Ready(Ok(self.device.read_bulk(self.endpoint, self.length)))
+    }
+}
+
+pub struct BulkWriteTransfer<'a> {
+    device: &'a UsbDevice,
+    endpoint: &'a UsbEndpoint,
+    data: &'a [u8],
+}
+
+impl Future for BulkWriteTransfer<'_> {
+    type Output = Result<(), TransferError>;
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.device.write_bulk(self.endpoint, self.data);
+        Poll::Ready(Ok(()))
+    }
+}
+
+pub struct ControlReadTransfer<'a> {
+    device: &'a UsbDevice,
+    setup: Option<ControlSetup>,
+    length: u16,
+}
+
+impl Future for ControlReadTransfer<'_> {
+    type Output = Result<Vec<u8>, TransferError>;
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let setup = self.setup.take().expect("ControlReadTransfer polled after completion");
+        Poll::Ready(Ok(self.device.read_control(setup, self.length)))
+    }
+}
+
+/// Async transfer surface mirroring `UsbDevice`'s blocking `read_interrupt` /
+/// `read_bulk` / `write_bulk` / `read_control`, so a caller can `select!`
+/// across several endpoints (e.g. poll a gamepad while streaming from a
+/// mass-storage device) on a single-threaded executor instead of blocking
+/// the whole component in one endpoint's read loop.
+pub trait UsbDeviceAsyncExt {
+    fn read_interrupt_async<'a>(
+        &'a self,
+        endpoint: &'a UsbEndpoint,
+        length: u64,
+    ) -> InterruptTransfer<'a>;
+    fn read_bulk_async<'a>(&'a self, endpoint: &'a UsbEndpoint, length: u64)
+        -> BulkReadTransfer<'a>;
+    fn write_bulk_async<'a>(
+        &'a self,
+        endpoint: &'a UsbEndpoint,
+        data: &'a [u8],
+    ) -> BulkWriteTransfer<'a>;
+    fn read_control_async(&self, setup: ControlSetup, length: u16) -> ControlReadTransfer<'_>;
+}
+
+impl UsbDeviceAsyncExt for UsbDevice {
+    fn read_interrupt_async<'a>(
+        &'a self,
+        endpoint: &'a UsbEndpoint,
+        length: u64,
+    ) -> InterruptTransfer<'a> {
+        InterruptTransfer {
+            device: self,
+            endpoint,
+            length,
+        }
+    }
+
+    fn read_bulk_async<'a>(
+        &'a self,
+        endpoint: &'a UsbEndpoint,
+        length: u64,
+    ) -> BulkReadTransfer<'a> {
+        BulkReadTransfer {
+            device: self,
+            endpoint,
+            length,
+        }
+    }
+
+    fn write_bulk_async<'a>(
+        &'a self,
+        endpoint: &'a UsbEndpoint,
+        data: &'a [u8],
+    ) -> BulkWriteTransfer<'a> {
+        BulkWriteTransfer {
+            device: self,
+            endpoint,
+            data,
+        }
+    }
+
+    fn read_control_async(&self, setup: ControlSetup, length: u16) -> ControlReadTransfer<'_> {
+        ControlReadTransfer {
+            device: self,
+            setup: Some(setup),
+            length,
+        }
+    }
+}
+
+/// A `futures`-compatible stream over an interrupt endpoint, so a polling
+/// `loop { read_interrupt(...) }` can become `while let Some(report) =
+/// stream.next().await`.
+pub struct InterruptStream<'a> {
+    device: &'a UsbDevice,
+    endpoint: &'a UsbEndpoint,
+    length: u64,
+}
+
+impl<'a> InterruptStream<'a> {
+    pub fn new(device: &'a UsbDevice, endpoint: &'a UsbEndpoint, length: u64) -> Self {
+        Self {
+            device,
+            endpoint,
+            length,
+        }
+    }
+}
+
+impl Stream for InterruptStream<'_> {
+    type Item = Result<Vec<u8>, TransferError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut transfer = InterruptTransfer {
+            device: self.device,
+            endpoint: self.endpoint,
+            length: self.length,
+        };
+        Pin::new(&mut transfer).poll(cx).map(Some)
+    }
+}
+
+/// Minimal `StreamExt::next` so callers don't need to pull in `futures_util`
+/// just for this one combinator.
+pub trait StreamExt: Stream {
+    fn next(&mut self) -> Next<'_, Self>
+    where
+        Self: Unpin,
+    {
+        Next { stream: self }
+    }
+}
+
+impl<S: Stream + ?Sized> StreamExt for S {}
+
+pub struct Next<'a, S: ?Sized> {
+    stream: &'a mut S,
+}
+
+impl<S: Stream + Unpin + ?Sized> Future for Next<'_, S> {
+    type Output = Option<S::Item>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.stream).poll_next(cx)
+    }
+}
+
+fn noop_raw_waker() -> RawWaker {
+    fn no_op(_: *const ()) {}
+    fn clone(_: *const ()) -> RawWaker {
+        noop_raw_waker()
+    }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, no_op, no_op, no_op);
+    RawWaker::new(std::ptr::null(), &VTABLE)
+}
+
+/// Drives a future to completion on the current thread. Every future in
+/// this module resolves on its first `poll` today, so this never actually
+/// has to wait on the waker; it exists so the example can be written the
+/// way it will read once the host interface supports real pending
+/// transfers.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use usb_wasm_bindings::device::{UsbDevice, UsbEndpoint};
+use usb_wasm_bindings::types::{ControlSetup, ControlSetupRecipient, ControlSetupType};
+
+const GET_DESCRIPTOR: u8 = 0x06;
+const HID_DESCRIPTOR_TYPE_REPORT: u16 = 0x22;
+
+const USAGE_PAGE_GENERIC_DESKTOP: u16 = 0x01;
+const USAGE_PAGE_BUTTON: u16 = 0x09;
+
+/// Fetches interface `interface_number`'s HID report descriptor (HID spec
+/// section 7.1.1: a standard `GET_DESCRIPTOR` request, but recipient
+/// Interface rather than Device). `length` only needs to be large enough to
+/// cover the whole descriptor; callers that don't know the exact size up
+/// front can over-ask and rely on [`parse_report_descriptor`] stopping at
+/// the first malformed/short item.
+pub fn read_report_descriptor(device: &UsbDevice, interface_number: u8, length: u16) -> Vec<u8> {
+    device.read_control(
+        ControlSetup {
+            request_type: ControlSetupType::Standard,
+            request_recipient: ControlSetupRecipient::Interface,
+            request: GET_DESCRIPTOR,
+            value: HID_DESCRIPTOR_TYPE_REPORT << 8,
+            index: interface_number as u16,
+        },
+        length,
+    )
+}
+
+/// One `Input`/`Output` main item: the report bits it covers, how to
+/// interpret them (logical range, usage page, the per-bit-chunk usages a
+/// Local item assigned it), and which numbered report it belongs to.
+#[derive(Debug, Clone)]
+struct ReportField {
+    report_id: u8,
+    bit_offset: u32,
+    bit_size: u32,
+    count: u32,
+    logical_minimum: i32,
+    logical_maximum: i32,
+    usage_page: u16,
+    usages: Vec<u16>,
+    is_constant: bool,
+    is_output: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct GlobalState {
+    usage_page: u16,
+    logical_minimum: i32,
+    logical_maximum: i32,
+    report_size: u32,
+    report_count: u32,
+    report_id: u8,
+}
+
+/// Parses a HID report descriptor's item stream (HID spec section 6.2.2)
+/// into the flat list of Input/Output fields needed to decode/encode actual
+/// reports, walking bit offsets from the Report Size/Count/ID items
+/// instead of assuming any fixed report layout.
+fn parse_report_descriptor(bytes: &[u8]) -> Vec<ReportField> {
+    let mut fields = Vec::new();
+    let mut global = GlobalState::default();
+    let mut global_stack = Vec::new();
+    let mut usages: Vec<u16> = Vec::new();
+    let mut input_bit_offsets: HashMap<u8, u32> = HashMap::new();
+    let mut output_bit_offsets: HashMap<u8, u32> = HashMap::new();
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let prefix = bytes[i];
+        i += 1;
+        let size = match prefix & 0x03 {
+            3 => 4,
+            n => n as usize,
+        };
+        if i + size > bytes.len() {
+            break;
+        }
+        let item_type = (prefix >> 2) & 0x03;
+        let tag = (prefix >> 4) & 0x0F;
+        let raw = read_item_bytes(&bytes[i..i + size]);
+        i += size;
+
+        match item_type {
+            // Global
+            1 => match tag {
+                0x0 => global.usage_page = raw as u16,
+                0x1 => global.logical_minimum = sign_extend_bytes(raw, size),
+                0x2 => global.logical_maximum = sign_extend_bytes(raw, size),
+                0x7 => global.report_size = raw,
+                0x8 => global.report_id = raw as u8,
+                0x9 => global.report_count = raw,
+                0xA => global_stack.push(global),
+                0xB => global = global_stack.pop().unwrap_or_default(),
+                _ => {}
+            },
+            // Local
+            2 => {
+                if tag == 0x0 {
+                    usages.push(raw as u16);
+                }
+            }
+            // Main
+            0 => {
+                if tag == 0x8 || tag == 0x9 {
+                    let is_output = tag == 0x9;
+                    let offsets = if is_output {
+                        &mut output_bit_offsets
+                    } else {
+                        &mut input_bit_offsets
+                    };
+                    let bit_offset = *offsets.entry(global.report_id).or_insert(0);
+                    fields.push(ReportField {
+                        report_id: global.report_id,
+                        bit_offset,
+                        bit_size: global.report_size,
+                        count: global.report_count,
+                        logical_minimum: global.logical_minimum,
+                        logical_maximum: global.logical_maximum,
+                        usage_page: global.usage_page,
+                        usages: usages.clone(),
+                        is_constant: raw & 0x1 != 0,
+                        is_output,
+                    });
+                    *offsets.get_mut(&global.report_id).unwrap() +=
+                        global.report_size * global.report_count;
+                }
+                // Every Main item (Input, Output, Collection, End Collection, ...)
+                // clears the Local items that applied to it.
+                usages.clear();
+            }
+            _ => {}
+        }
+    }
+
+    fields
+}
+
+fn read_item_bytes(data: &[u8]) -> u32 {
+    data.iter()
+        .enumerate()
+        .fold(0u32, |acc, (i, &b)| acc | (u32::from(b) << (8 * i)))
+}
+
+fn sign_extend_bytes(value: u32, size: usize) -> i32 {
+    match size {
+        1 => value as i8 as i32,
+        2 => value as i16 as i32,
+        _ => value as i32,
+    }
+}
+
+fn sign_extend_bits(value: u32, bit_size: u32) -> i32 {
+    if bit_size == 0 || bit_size >= 32 {
+        return value as i32;
+    }
+    let shift = 32 - bit_size;
+    ((value << shift) as i32) >> shift
+}
+
+fn read_bits(data: &[u8], bit_offset: u32, bit_size: u32) -> u32 {
+    let mut value = 0u32;
+    for bit in 0..bit_size.min(32) {
+        let pos = bit_offset + bit;
+        let byte_index = (pos / 8) as usize;
+        if byte_index >= data.len() {
+            break;
+        }
+        let bit_value = (data[byte_index] >> (pos % 8)) & 1;
+        value |= u32::from(bit_value) << bit;
+    }
+    value
+}
+
+fn generic_desktop_axis_name(usage: u16) -> Option<&'static str> {
+    match usage {
+        0x30 => Some("x"),
+        0x31 => Some("y"),
+        0x32 => Some("z"),
+        0x33 => Some("rx"),
+        0x34 => Some("ry"),
+        0x35 => Some("rz"),
+        0x36 => Some("slider"),
+        0x37 => Some("dial"),
+        0x38 => Some("wheel"),
+        _ => None,
+    }
+}
+
+/// A decoded gamepad input report: which buttons are currently held, and
+/// every named axis the report descriptor exposed, normalized to
+/// `-1.0..=1.0` regardless of the underlying logical range.
+#[derive(Debug, Clone, Default)]
+pub struct GamepadState {
+    pub buttons: u32,
+    pub axes: HashMap<String, f32>,
+}
+
+impl GamepadState {
+    pub fn button(&self, index: u32) -> bool {
+        index < 32 && self.buttons & (1 << index) != 0
+    }
+
+    pub fn axis(&self, name: &str) -> f32 {
+        self.axes.get(name).copied().unwrap_or(0.0)
+    }
+}
+
+/// A HID gamepad whose input/output report layout was learned from its own
+/// report descriptor rather than hardcoded, so it works with any
+/// standard-HID-class controller, not just one specific vendor/product.
+///
+/// This deliberately doesn't apply to the Xbox One controller `xbox`/
+/// `xbox-maze` otherwise talk to: that device exposes a vendor-specific
+/// (non-HID) interface with no report descriptor at all, which is exactly
+/// why its own input parsing still reads fixed byte offsets. `HidGamepad`
+/// is for the general case; [`set_rumble`] below still has to be
+/// device-specific, since force feedback output reports aren't implied by
+/// the generic Input-field decode above.
+pub struct HidGamepad {
+    device: UsbDevice,
+    endpoint_in: UsbEndpoint,
+    fields: Vec<ReportField>,
+}
+
+impl HidGamepad {
+    /// Fetches and parses `interface_number`'s report descriptor, then
+    /// reads reports from `endpoint_in`. Does not open the device or claim
+    /// the interface -- the caller does that the same way every other
+    /// command-component does, since it may need the same device/interface
+    /// for other endpoints too.
+    pub fn new(device: UsbDevice, interface_number: u8, endpoint_in: UsbEndpoint) -> Self {
+        let descriptor = read_report_descriptor(&device, interface_number, 4096);
+        let fields = parse_report_descriptor(&descriptor);
+        HidGamepad {
+            device,
+            endpoint_in,
+            fields,
+        }
+    }
+
+    pub fn read_state(&self) -> GamepadState {
+        let length = self.endpoint_in.descriptor().max_packet_size as u64;
+        let report = self.device.read_interrupt(&self.endpoint_in, length);
+        self.decode(&report)
+    }
+
+    fn decode(&self, report: &[u8]) -> GamepadState {
+        let mut state = GamepadState::default();
+        let has_report_ids = self.fields.iter().any(|field| field.report_id != 0);
+        let (report_id, data) = if has_report_ids && !report.is_empty() {
+            (report[0], &report[1..])
+        } else {
+            (0, report)
+        };
+
+        let mut button_index = 0u32;
+        for field in &self.fields {
+            if field.is_output || field.is_constant || field.report_id != report_id {
+                continue;
+            }
+
+            for n in 0..field.count {
+                let offset = field.bit_offset + n * field.bit_size;
+                let raw = read_bits(data, offset, field.bit_size);
+
+                match field.usage_page {
+                    USAGE_PAGE_BUTTON => {
+                        if raw != 0 {
+                            state.buttons |= 1 << button_index;
+                        }
+                        button_index += 1;
+                    }
+                    USAGE_PAGE_GENERIC_DESKTOP => {
+                        let usage = field.usages.get(n as usize).copied().unwrap_or(0);
+                        if let Some(name) = generic_desktop_axis_name(usage) {
+                            let value = if field.logical_minimum < 0 {
+                                sign_extend_bits(raw, field.bit_size)
+                            } else {
+                                raw as i32
+                            };
+                            let range = (field.logical_maximum - field.logical_minimum) as f32;
+                            let normalized = if range > 0.0 {
+                                (2.0 * (value - field.logical_minimum) as f32 / range) - 1.0
+                            } else {
+                                0.0
+                            };
+                            state.axes.insert(name.to_owned(), normalized);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        state
+    }
+}
+
+/// Sends the Xbox One controller's rumble output report on its interrupt
+/// OUT endpoint, per https://github.com/quantus/xbox-one-controller-protocol
+/// (the same reference `xbox-maze` already cites for the initial setup
+/// report): a fixed 15-byte packet starting `0x09 0x00`, with the two motor
+/// magnitudes in bytes 10/11.
+pub fn set_rumble(device: &UsbDevice, endpoint_out: &UsbEndpoint, strong: u8, weak: u8) {
+    let report = [
+        0x09, 0x00, 0x00, 0x09, 0x00, 0x0F, 0x00, 0x00, 0x00, 0x00, strong, weak, 0xFF, 0x00,
+        0x00,
+    ];
+    device.write_interrupt(endpoint_out, &report);
+}
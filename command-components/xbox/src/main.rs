@@ -1,5 +1,8 @@
+mod async_transfer;
+mod hid;
+
 use usb_wasm_bindings::device::UsbDevice;
-use usb_wasm_bindings::types::Filter;
+use usb_wasm_bindings::types::{Direction, Filter};
 
 use std::io;
 use std::io::Write;
@@ -8,6 +11,8 @@ use anyhow::anyhow;
 use byteorder::ByteOrder;
 use colored::Colorize;
 
+use async_transfer::{block_on, InterruptStream, StreamExt};
+
 #[derive(Copy, Clone, Debug, Default)]
 pub struct XboxControllerState {
     a: bool,
@@ -77,6 +82,10 @@ impl std::fmt::Display for XboxControllerState {
     }
 }
 
+// The Xbox One controller's interface is vendor-specific (not USB HID), so
+// it has no report descriptor to parse -- this fixed-offset decode is the
+// correct approach here, unlike a true HID gamepad, which should go through
+// `hid::HidGamepad` instead.
 pub fn parse_xbox_controller_data(data: &[u8]) -> XboxControllerState {
     assert!(data.len() >= 18, "data is too short");
     let lt = byteorder::LittleEndian::read_u16(&data[6..]) as f32 / 1023.0;
@@ -141,6 +150,10 @@ pub fn main() -> anyhow::Result<()> {
                 && e.descriptor().endpoint_number == 0x01
         })
         .ok_or(anyhow!("Could not find endpoint"))?;
+    let endpoint_out = interface
+        .endpoints()
+        .into_iter()
+        .find(|e| e.descriptor().direction == Direction::Out);
 
     // Open device
     xbox_controller.open();
@@ -148,28 +161,40 @@ pub fn main() -> anyhow::Result<()> {
     xbox_controller.claim_interface(&interface);
 
     println!("Connected to Xbox Controller");
+    // Give a short rumble pulse to confirm the controller is connected and,
+    // for controllers that expose an OUT endpoint, that force feedback works.
+    if let Some(endpoint_out) = &endpoint_out {
+        hid::set_rumble(&xbox_controller, endpoint_out, 0x80, 0x80);
+    }
     let mut previous_length = 0;
 
     print!("\r{} ", XboxControllerState::default()); //Print empty values first untill we get our first communication
     io::stdout().flush()?;
 
-    loop {
-        let data =
-            xbox_controller.read_interrupt(&endpoint, endpoint.descriptor().max_packet_size as u64);
-        if data.len() == 18 {
-            let state = parse_xbox_controller_data(&data[0..18]);
-            let state_str = state.to_string();
-            if state_str.len() < previous_length {
-                print!(
-                    "\r{}{} ",
-                    state,
-                    " ".repeat(previous_length - state_str.len())
-                );
-            } else {
-                print!("\r{} ", state);
+    block_on(async {
+        let mut stream = InterruptStream::new(
+            &xbox_controller,
+            &endpoint,
+            endpoint.descriptor().max_packet_size as u64,
+        );
+        while let Some(data) = stream.next().await {
+            let data = data?;
+            if data.len() == 18 {
+                let state = parse_xbox_controller_data(&data[0..18]);
+                let state_str = state.to_string();
+                if state_str.len() < previous_length {
+                    print!(
+                        "\r{}{} ",
+                        state,
+                        " ".repeat(previous_length - state_str.len())
+                    );
+                } else {
+                    print!("\r{} ", state);
+                }
+                io::stdout().flush()?;
+                previous_length = state_str.len();
             }
-            io::stdout().flush()?;
-            previous_length = state_str.len();
         }
-    }
+        Ok::<(), anyhow::Error>(())
+    })
 }
@@ -1,66 +1,174 @@
 use anyhow::anyhow;
+use clap::Parser;
 use tracing_subscriber::EnvFilter;
-use wasmtime::component::{Component, Linker};
+use wasmtime::component::{Component, Linker, Type, Val};
 use wasmtime::{Config, Engine, Store};
+use usb_wasm::{UsbPolicy, UsbPolicyHost};
+use wasmtime_usb_cli::cli::{parse_env, parse_preopen, parse_usb_rule, Args};
+use wasmtime_usb_cli::run::run;
 use wasmtime_usb_cli::HostState;
-use wasmtime_wasi::{I32Exit, WasiView};
 
 fn main() -> anyhow::Result<()> {
     // Set up logging
     tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env()).init();
-    
-    // TODO create a proper CLI here
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        println!("Usage: {} <command component>", args[0]);
-        return Ok(());
-    }
 
-    let command_component_path = std::path::Path::new(&args[1]);
+    let args = Args::parse();
+
+    let command_component_path = args.component.as_path();
 
     // Configure an `Engine` and link in all the host components (Wasi preview 2 and our USB component)
     let config = {
         let mut config = Config::new();
         config.wasm_component_model(true);
-        config.profiler(wasmtime::ProfilingStrategy::PerfMap);
+        config.profiler(args.profile.into());
+        config.cache_config_load_default()?;
         config
     };
     let engine = Engine::new(&config)?;
     let mut linker: Linker<HostState> = wasmtime::component::Linker::new(&engine);
     register_host_components(&mut linker)?;
 
-    // Set up the Store with the command line arguments
-    let args = std::env::args().skip(1).collect::<Vec<_>>();
-    let mut store = Store::new(&engine, HostState::new(&args));
-
-    // Load the component (should be an instance of the wasi command component)
-    let component = Component::from_file(&engine, command_component_path)?;
-    let (bindings, _instance) =
-        wasmtime_wasi::command::sync::Command::instantiate(&mut store, &component, &linker)?;
-
-    // Here our `greet` function doesn't take any parameters for the component,
-    // but in the Wasmtime embedding API the first argument is always a `Store`.
-    let result = bindings.wasi_cli_run().call_run(&mut store);
-    // .expect("failed to invoke 'run' function");
-
-    match result {
-        Ok(Ok(())) => Ok(()),
-        Ok(Err(())) => Err(anyhow!("inner error")), // IDK HOW THIS IS CAUSED
-        Err(e) => {
-            if let Some(source) = e.source() {
-                if let Some(exit_code) = source.downcast_ref::<I32Exit>() {
-                    std::process::exit(exit_code.process_exit_code());
-                    // return Err(exit_code.into());
-                }
-                println!("Source: {}", source);
-            }
-            println!("e: {}", e);
-            Ok(())
+    // Set up the Store with the command line arguments, preopened directories, and env
+    let preopens = args
+        .dirs
+        .iter()
+        .map(|dir| parse_preopen(dir))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let envs = args
+        .envs
+        .iter()
+        .map(|env| parse_env(env))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let usb_policy = UsbPolicy {
+        allow: args
+            .allow_usb
+            .iter()
+            .map(|rule| parse_usb_rule(rule))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        deny: args
+            .deny_usb
+            .iter()
+            .map(|rule| parse_usb_rule(rule))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+    };
+
+    let mut guest_args = args.guest_args.clone();
+    guest_args.insert(
+        0,
+        command_component_path.to_string_lossy().into_owned(),
+    );
+    let mut store = Store::new(
+        &engine,
+        HostState::new_with_policy(&guest_args, &preopens, &envs, usb_policy)?,
+    );
+
+    // Load the component (it may be a `wasi:cli` command, or a reactor we
+    // invoke a named export on directly)
+    let component = load_component(&engine, command_component_path, args.emit_cwasm.as_deref())?;
+
+    if let Some(export_name) = &args.invoke {
+        return invoke_export(&mut store, &component, &linker, export_name, &args.guest_args);
+    }
+
+    match run(&mut store, &component, &linker) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            tracing::error!("{err}");
+            std::process::exit(err.into());
         }
     }
 }
 
-fn register_host_components<T: WasiView>(linker: &mut Linker<T>) -> anyhow::Result<()> {
+/// Loads `path` as a component, taking the AOT fast path when it's already a
+/// precompiled `.cwasm` artifact. Otherwise compiles it from source and, if
+/// `emit_cwasm` is given, serializes the result there for a later run to pick
+/// up directly.
+///
+/// # Safety
+///
+/// `Component::deserialize_file` trusts that the `.cwasm` was produced by a
+/// compatible Wasmtime build and hasn't been tampered with; we only take that
+/// path for files the caller explicitly pointed us at.
+fn load_component(
+    engine: &Engine,
+    path: &std::path::Path,
+    emit_cwasm: Option<&std::path::Path>,
+) -> anyhow::Result<Component> {
+    if path.extension().is_some_and(|ext| ext == "cwasm") {
+        return unsafe { Component::deserialize_file(engine, path) };
+    }
+
+    let component = Component::from_file(engine, path)?;
+    if let Some(emit_path) = emit_cwasm {
+        std::fs::write(emit_path, component.serialize()?)?;
+    }
+    Ok(component)
+}
+
+/// Instantiate `component` generically (rather than via the `wasi:cli`
+/// command bindings) and call the export named `export_name`, parsing
+/// `raw_args` into the export's parameter types positionally.
+fn invoke_export(
+    store: &mut Store<HostState>,
+    component: &Component,
+    linker: &Linker<HostState>,
+    export_name: &str,
+    raw_args: &[String],
+) -> anyhow::Result<()> {
+    let instance = linker.instantiate(&mut *store, component)?;
+    let func = instance
+        .get_func(&mut *store, export_name)
+        .ok_or_else(|| anyhow!("component has no export named `{export_name}`"))?;
+
+    let param_types = func.params(&store);
+    if raw_args.len() != param_types.len() {
+        return Err(anyhow!(
+            "export `{export_name}` takes {} argument(s), got {}",
+            param_types.len(),
+            raw_args.len()
+        ));
+    }
+
+    let params = raw_args
+        .iter()
+        .zip(param_types.iter())
+        .map(|(raw, ty)| parse_val(ty, raw))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut results = vec![Val::Bool(false); func.results(&store).len()];
+    func.call(&mut *store, &params, &mut results)?;
+    func.post_return(&mut *store)?;
+
+    for result in &results {
+        println!("{result:?}");
+    }
+
+    Ok(())
+}
+
+/// Parses a single CLI argument into a component `Val` according to the
+/// expected WIT type. Only covers the primitive types a trailing CLI arg can
+/// reasonably encode.
+fn parse_val(ty: &Type, raw: &str) -> anyhow::Result<Val> {
+    Ok(match ty {
+        Type::Bool => Val::Bool(raw.parse()?),
+        Type::S8 => Val::S8(raw.parse()?),
+        Type::U8 => Val::U8(raw.parse()?),
+        Type::S16 => Val::S16(raw.parse()?),
+        Type::U16 => Val::U16(raw.parse()?),
+        Type::S32 => Val::S32(raw.parse()?),
+        Type::U32 => Val::U32(raw.parse()?),
+        Type::S64 => Val::S64(raw.parse()?),
+        Type::U64 => Val::U64(raw.parse()?),
+        Type::Float32 => Val::Float32(raw.parse()?),
+        Type::Float64 => Val::Float64(raw.parse()?),
+        Type::Char => Val::Char(raw.parse()?),
+        Type::String => Val::String(raw.into()),
+        other => return Err(anyhow!("unsupported parameter type for --invoke: {other:?}")),
+    })
+}
+
+fn register_host_components<T: UsbPolicyHost>(linker: &mut Linker<T>) -> anyhow::Result<()> {
     wasmtime_wasi::command::sync::add_to_linker(linker)?;
     usb_wasm::add_to_linker(linker)?;
 
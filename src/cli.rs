@@ -0,0 +1,110 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+
+/// Launches a `wasi:cli` command (or reactor) component with the USB host
+/// component linked in.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// Strategy used to profile the running component.
+    #[arg(long, value_enum, default_value_t = ProfilingMode::None)]
+    pub profile: ProfilingMode,
+
+    /// Directory to preopen into the guest's WASI filesystem, in `host` or
+    /// `host::guest` form. May be passed multiple times.
+    #[arg(long = "dir", value_name = "HOST_PATH[::GUEST_PATH]")]
+    pub dirs: Vec<String>,
+
+    /// Instead of running the component as a `wasi:cli` command, instantiate
+    /// it generically and call the named export directly. Useful for reactor
+    /// components that don't export `wasi:cli/run`.
+    #[arg(long)]
+    pub invoke: Option<String>,
+
+    /// Allow the guest USB access only to devices matching `VID:PID` (hex).
+    /// May be passed multiple times; if omitted, every device not matched by
+    /// `--deny-usb` is allowed.
+    #[arg(long = "allow-usb", value_name = "VID:PID")]
+    pub allow_usb: Vec<String>,
+
+    /// Deny the guest USB access to devices matching `VID:PID` (hex). Takes
+    /// priority over `--allow-usb`. May be passed multiple times.
+    #[arg(long = "deny-usb", value_name = "VID:PID")]
+    pub deny_usb: Vec<String>,
+
+    /// Serialize the compiled component to this path after loading it, so a
+    /// later run can skip recompilation by pointing `component` at a
+    /// `.cwasm` file instead. Ignored when `component` is already a
+    /// precompiled artifact.
+    #[arg(long, value_name = "PATH")]
+    pub emit_cwasm: Option<PathBuf>,
+
+    /// Environment variable to forward to the guest, as `KEY=VALUE`. May be
+    /// passed multiple times.
+    #[arg(long = "env", value_name = "KEY=VALUE")]
+    pub envs: Vec<String>,
+
+    /// Path to the component to run.
+    pub component: PathBuf,
+
+    /// Arguments forwarded to the guest component.
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub guest_args: Vec<String>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ProfilingMode {
+    None,
+    Perfmap,
+    Jitdump,
+}
+
+impl From<ProfilingMode> for wasmtime::ProfilingStrategy {
+    fn from(mode: ProfilingMode) -> Self {
+        match mode {
+            ProfilingMode::None => wasmtime::ProfilingStrategy::None,
+            ProfilingMode::Perfmap => wasmtime::ProfilingStrategy::PerfMap,
+            ProfilingMode::Jitdump => wasmtime::ProfilingStrategy::JitDump,
+        }
+    }
+}
+
+/// A host directory preopened into the guest, optionally under a different
+/// guest-visible path.
+pub struct PreopenDir {
+    pub host_path: String,
+    pub guest_path: String,
+}
+
+pub fn parse_preopen(spec: &str) -> anyhow::Result<PreopenDir> {
+    match spec.split_once("::") {
+        Some((host_path, guest_path)) => Ok(PreopenDir {
+            host_path: host_path.to_owned(),
+            guest_path: guest_path.to_owned(),
+        }),
+        None => Ok(PreopenDir {
+            host_path: spec.to_owned(),
+            guest_path: spec.to_owned(),
+        }),
+    }
+}
+
+pub fn parse_env(spec: &str) -> anyhow::Result<(String, String)> {
+    spec.split_once('=')
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or_else(|| anyhow::anyhow!("invalid --env value `{spec}`, expected KEY=VALUE"))
+}
+
+/// Parses a `VID:PID` pair (hex, with or without a `0x` prefix) into a
+/// vendor/product USB policy rule.
+pub fn parse_usb_rule(spec: &str) -> anyhow::Result<usb_wasm::UsbPolicyRule> {
+    let (vendor_id, product_id) = spec
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("invalid USB rule `{spec}`, expected VID:PID"))?;
+    Ok(usb_wasm::UsbPolicyRule {
+        vendor_id: Some(u16::from_str_radix(vendor_id.trim_start_matches("0x"), 16)?),
+        product_id: Some(u16::from_str_radix(product_id.trim_start_matches("0x"), 16)?),
+        ..Default::default()
+    })
+}
@@ -1,8 +1,18 @@
-use wasmtime_wasi::preview2::{ResourceTable, WasiCtx, WasiCtxBuilder, WasiView};
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+use usb_wasm::{HotplugFilter, TransferHandle, UsbPolicy, UsbPolicyHost, Watcher};
+use wasmtime_wasi::preview2::{DirPerms, FilePerms, ResourceTable, WasiCtx, WasiCtxBuilder, WasiView};
+
+pub mod cli;
+pub mod run;
+
+use cli::PreopenDir;
 
 pub struct HostState {
     wasi_ctx: WasiCtx,
     wasi_table: ResourceTable,
+    usb_policy: UsbPolicy,
 }
 
 impl HostState {
@@ -12,8 +22,83 @@ impl HostState {
         Self {
             wasi_ctx,
             wasi_table,
+            usb_policy: UsbPolicy::allow_all(),
         }
     }
+
+    pub fn new_with_env(
+        args: &[impl AsRef<str>],
+        preopens: &[PreopenDir],
+        envs: &[(String, String)],
+    ) -> anyhow::Result<Self> {
+        Self::new_with_policy(args, preopens, envs, UsbPolicy::allow_all())
+    }
+
+    pub fn new_with_policy(
+        args: &[impl AsRef<str>],
+        preopens: &[PreopenDir],
+        envs: &[(String, String)],
+        usb_policy: UsbPolicy,
+    ) -> anyhow::Result<Self> {
+        let mut builder = WasiCtxBuilder::new();
+        builder.inherit_stdio().args(args).envs(envs);
+
+        for preopen in preopens {
+            builder.preopened_dir(
+                &preopen.host_path,
+                &preopen.guest_path,
+                DirPerms::all(),
+                FilePerms::all(),
+            )?;
+        }
+
+        Ok(Self {
+            wasi_ctx: builder.build(),
+            wasi_table: ResourceTable::new(),
+            usb_policy,
+        })
+    }
+}
+
+impl HostState {
+    /// Serves an already-enumerated USB device over the USB/IP network
+    /// protocol at `addr`, so a remote machine's kernel can attach to
+    /// hardware this process has opened without going through a WASM guest
+    /// at all. Blocks the calling thread for as long as the listener stays
+    /// up; see [`usb_wasm::export_usbip`] for the wire-level details.
+    pub fn export(
+        &mut self,
+        device: wasmtime::component::Resource<usb_wasm::UsbDevice>,
+        addr: impl ToSocketAddrs,
+    ) -> anyhow::Result<()> {
+        let device = self.wasi_table.get_mut(&device)?;
+        Ok(usb_wasm::export_usbip(device, addr)?)
+    }
+
+    /// Reads up to `length` bytes from a bulk endpoint, giving up after
+    /// `timeout` or as soon as `cancel` is triggered instead of blocking on
+    /// the device indefinitely. `cancel` can be shared with another thread
+    /// (or, once the guest side grows cancellable pollables, with the
+    /// component that issued the read) to abandon it early.
+    pub fn read_bulk_with_timeout(
+        &mut self,
+        device: wasmtime::component::Resource<usb_wasm::UsbDevice>,
+        endpoint: u8,
+        length: usize,
+        timeout: Duration,
+        cancel: &TransferHandle,
+    ) -> anyhow::Result<Vec<u8>> {
+        let device = self.wasi_table.get_mut(&device)?;
+        Ok(device.bulk_transfer_in_cancellable(endpoint, length, timeout, cancel)?)
+    }
+
+    /// Starts watching for USB devices matching `filter` arriving or
+    /// leaving, so an embedder can attach to an instrument whenever it's
+    /// plugged in instead of polling `enumerate()` on a timer. See
+    /// [`usb_wasm::watch`] for the libusb hotplug machinery behind this.
+    pub fn watch(&self, filter: HotplugFilter) -> anyhow::Result<Watcher> {
+        Ok(usb_wasm::watch(filter)?)
+    }
 }
 
 impl WasiView for HostState {
@@ -25,3 +110,9 @@ impl WasiView for HostState {
         &mut self.wasi_ctx
     }
 }
+
+impl UsbPolicyHost for HostState {
+    fn usb_policy(&self) -> &UsbPolicy {
+        &self.usb_policy
+    }
+}
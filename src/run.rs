@@ -0,0 +1,80 @@
+use wasmtime::component::{Component, Linker};
+use wasmtime::Store;
+use wasmtime_wasi::I32Exit;
+
+use crate::HostState;
+
+/// Classifies how a guest invocation ended, so `main` can map it onto a
+/// stable process exit code instead of pattern-matching `anyhow::Error`
+/// ad-hoc.
+#[derive(Debug)]
+pub enum RunError {
+    /// Failed to configure the engine, store, or USB policy.
+    Configuration(anyhow::Error),
+    /// Failed to instantiate the component against the linker.
+    Instantiation(anyhow::Error),
+    /// The component instantiated, but its `run` export returned `Err(())`,
+    /// i.e. the guest signalled its own failure.
+    CallFailed,
+    /// A WASI preview 2 trap (other than a guest-requested exit) unwound the
+    /// call.
+    Wasi(anyhow::Error),
+    /// The guest called `proc_exit` with this code.
+    GuestExit(i32),
+}
+
+impl std::fmt::Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::Configuration(e) => write!(f, "configuration error: {e}"),
+            RunError::Instantiation(e) => write!(f, "failed to instantiate component: {e}"),
+            RunError::CallFailed => write!(f, "guest `run` export returned an error"),
+            RunError::Wasi(e) => write!(f, "guest trapped: {e}"),
+            RunError::GuestExit(code) => write!(f, "guest exited with code {code}"),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+impl From<RunError> for i32 {
+    fn from(err: RunError) -> Self {
+        match err {
+            RunError::Configuration(_) => 2,
+            RunError::Instantiation(_) => 3,
+            RunError::CallFailed => 1,
+            RunError::Wasi(_) => 70, // EX_SOFTWARE
+            RunError::GuestExit(code) => code,
+        }
+    }
+}
+
+/// Instantiates `component` as a `wasi:cli` command and runs it to
+/// completion, classifying the outcome as a [`RunError`] rather than
+/// guessing at `anyhow::Error` internals.
+pub fn run(
+    store: &mut Store<HostState>,
+    component: &Component,
+    linker: &Linker<HostState>,
+) -> Result<(), RunError> {
+    let (bindings, _instance) =
+        wasmtime_wasi::command::sync::Command::instantiate(&mut *store, component, linker)
+            .map_err(RunError::Instantiation)?;
+
+    match bindings.wasi_cli_run().call_run(&mut *store) {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(())) => Err(RunError::CallFailed),
+        Err(e) => {
+            if let Some(exit) = e
+                .source()
+                .and_then(|source| source.downcast_ref::<I32Exit>())
+            {
+                return Err(RunError::GuestExit(exit.process_exit_code()));
+            }
+            if let Some(exit) = e.downcast_ref::<I32Exit>() {
+                return Err(RunError::GuestExit(exit.process_exit_code()));
+            }
+            Err(RunError::Wasi(e))
+        }
+    }
+}
@@ -0,0 +1,433 @@
+//! Cancellable, timed-out bulk and interrupt transfers.
+//!
+//! `UsbDevice::bulk_transfer_in`/`interrupt_transfer_in` (and their `_out`
+//! counterparts) go through `rusb`'s synchronous API, which blocks the
+//! calling thread until the device responds -- there's no way to give up on
+//! a stalled endpoint early. This module drives the same raw libusb async
+//! transfer machinery `UsbDevice::iso_transfer_in`/`iso_transfer_out`
+//! already use, but for bulk/interrupt transfers, with a caller-supplied
+//! deadline and a [`TransferHandle`] either side can use to cancel early.
+//!
+//! [`submit_and_wait`] (and the `*_cancellable` methods built on it) still
+//! block the calling thread for the whole transfer; [`SubmittedTransfer`]
+//! decouples submission from waiting so a caller can poll completion
+//! without blocking instead.
+
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rusb::constants::{LIBUSB_TRANSFER_TYPE_BULK, LIBUSB_TRANSFER_TYPE_INTERRUPT};
+use rusb::ffi::{
+    libusb_alloc_transfer, libusb_cancel_transfer, libusb_free_transfer,
+    libusb_handle_events_completed, libusb_submit_transfer, libusb_transfer,
+};
+use rusb::{DeviceHandle, GlobalContext, UsbContext};
+
+use crate::error::UsbWasmError;
+use crate::error_from_libusb;
+
+// The libusb_transfer_status values (libusb/libusb.h) this module checks
+// for; `IsoPacketStatus::from_libusb` maps the full set for iso packets.
+const LIBUSB_TRANSFER_COMPLETED: i32 = 0;
+const LIBUSB_TRANSFER_CANCELLED: i32 = 3;
+
+/// How often [`SubmittedTransfer`]'s background thread wakes up to pump
+/// libusb's event loop. Shorter means completions are noticed sooner;
+/// [`hotplug::watch`](crate::hotplug::watch) uses the same tradeoff for its
+/// own poller thread.
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often [`SubmittedTransfer::wait`] re-checks completion while it
+/// blocks the calling thread.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+extern "system" fn on_complete(transfer: *mut libusb_transfer) {
+    unsafe {
+        *((*transfer).user_data as *mut i32) = 1;
+    }
+}
+
+/// A cooperative cancellation token for one in-flight transfer. Cloning
+/// shares the same underlying flag, so a caller can hand one half to the
+/// thread driving [`submit_and_wait`] and keep the other to cancel it from
+/// elsewhere (e.g. in response to the guest dropping its pollable).
+#[derive(Clone, Default)]
+pub struct TransferHandle(Arc<AtomicBool>);
+
+impl TransferHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Submits a single bulk or interrupt transfer and blocks until it
+/// completes, the deadline passes, or `cancel` is set -- whichever comes
+/// first -- returning the data actually transferred either way.
+fn submit_and_wait(
+    handle: &DeviceHandle<GlobalContext>,
+    endpoint: u8,
+    transfer_type: u8,
+    mut buffer: Vec<u8>,
+    timeout: Duration,
+    cancel: &TransferHandle,
+) -> Result<Vec<u8>, UsbWasmError> {
+    let transfer = unsafe { libusb_alloc_transfer(0) };
+    let transfer_ref = unsafe { &mut *transfer };
+
+    let mut completed = 0_i32;
+    let completed_ptr = (&mut completed) as *mut i32;
+
+    transfer_ref.dev_handle = handle.as_raw();
+    transfer_ref.endpoint = endpoint;
+    transfer_ref.transfer_type = transfer_type;
+    // We drive our own deadline below (so `cancel` can also take effect
+    // early); libusb's own timeout is left disabled.
+    transfer_ref.timeout = 0;
+    transfer_ref.buffer = buffer.as_mut_ptr();
+    transfer_ref.length = buffer.len() as _;
+    transfer_ref.user_data = completed_ptr as *mut _;
+    transfer_ref.callback = on_complete;
+
+    let err = unsafe { libusb_submit_transfer(transfer) };
+    if err != 0 {
+        unsafe { libusb_free_transfer(transfer) };
+        return Err(error_from_libusb(err).into());
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut cancel_requested = false;
+    let mut handle_events_err = 0;
+    while completed == 0 {
+        if !cancel_requested && (cancel.is_cancelled() || Instant::now() >= deadline) {
+            // Cancelling doesn't complete the transfer immediately -- libusb
+            // still delivers a completion callback (with status
+            // `LIBUSB_TRANSFER_CANCELLED`) once the kernel confirms it, so we
+            // keep polling for `completed` instead of freeing `transfer`/
+            // `buffer` out from under that callback.
+            unsafe { libusb_cancel_transfer(transfer) };
+            cancel_requested = true;
+        }
+        handle_events_err =
+            unsafe { libusb_handle_events_completed(handle.context().as_raw(), completed_ptr) };
+        if handle_events_err != 0 {
+            break;
+        }
+    }
+
+    let status = transfer_ref.status;
+    let actual_length = transfer_ref.actual_length;
+    unsafe { libusb_free_transfer(transfer) };
+
+    if handle_events_err != 0 {
+        return Err(error_from_libusb(handle_events_err).into());
+    }
+    if status == LIBUSB_TRANSFER_CANCELLED {
+        return Err(if cancel.is_cancelled() {
+            UsbWasmError::Cancelled
+        } else {
+            UsbWasmError::Timeout
+        });
+    }
+    if status != LIBUSB_TRANSFER_COMPLETED {
+        // TODO: map the remaining libusb_transfer_status values (STALL,
+        // NO_DEVICE, OVERFLOW, ...) to more specific UsbWasmError variants.
+        return Err(UsbWasmError::Timeout);
+    }
+
+    buffer.truncate(actual_length as usize);
+    Ok(buffer)
+}
+
+/// A bulk or interrupt transfer that's been submitted but not yet waited on.
+///
+/// Unlike [`submit_and_wait`], which commits the calling thread to sitting in
+/// `libusb_handle_events_completed` for as long as the transfer takes,
+/// `SubmittedTransfer` hands completion-pumping off to a background thread
+/// (the same trick [`crate::hotplug::watch`]'s poller thread uses) so
+/// [`Self::poll`] can report completion without blocking. Wiring that into a
+/// WASI `pollable` so a guest can `await` it alongside other I/O needs
+/// wasmtime's async instantiation support, which this embedder doesn't use
+/// yet. For now this only buys a
+/// host-side caller (e.g. a future async `HostState` method) a non-blocking
+/// check and a real timeout.
+pub struct SubmittedTransfer {
+    transfer: *mut libusb_transfer,
+    completed: Arc<AtomicI32>,
+    buffer: Vec<u8>,
+    cancel: TransferHandle,
+    poller: Option<JoinHandle<()>>,
+}
+
+// `transfer` is only read by this struct's own methods (all of which take
+// `&self`/`&mut self`, so there's no concurrent access) and by the libusb
+// worker threads libusb itself manages internally.
+unsafe impl Send for SubmittedTransfer {}
+
+impl Drop for SubmittedTransfer {
+    /// Cleans up if the caller drops a `SubmittedTransfer` without ever
+    /// calling [`Self::wait`] -- cancels it, waits for the background
+    /// thread's confirmation so the callback can't fire into freed memory,
+    /// then frees the underlying `libusb_transfer`. A no-op after `wait`,
+    /// which already does this itself via [`Self::finish`].
+    fn drop(&mut self) {
+        if let Some(poller) = self.poller.take() {
+            self.cancel();
+            let _ = poller.join();
+            unsafe { libusb_free_transfer(self.transfer) };
+        }
+    }
+}
+
+impl SubmittedTransfer {
+    fn submit(
+        handle: &DeviceHandle<GlobalContext>,
+        endpoint: u8,
+        transfer_type: u8,
+        mut buffer: Vec<u8>,
+        cancel: TransferHandle,
+    ) -> Result<Self, UsbWasmError> {
+        let transfer = unsafe { libusb_alloc_transfer(0) };
+        let transfer_ref = unsafe { &mut *transfer };
+
+        let completed = Arc::new(AtomicI32::new(0));
+        let completed_ptr = completed.as_ptr();
+
+        transfer_ref.dev_handle = handle.as_raw();
+        transfer_ref.endpoint = endpoint;
+        transfer_ref.transfer_type = transfer_type;
+        // Same reasoning as `submit_and_wait`: we enforce the deadline
+        // ourselves so `cancel` can also take effect early.
+        transfer_ref.timeout = 0;
+        transfer_ref.buffer = buffer.as_mut_ptr();
+        transfer_ref.length = buffer.len() as _;
+        transfer_ref.user_data = completed_ptr as *mut _;
+        transfer_ref.callback = on_complete;
+
+        let err = unsafe { libusb_submit_transfer(transfer) };
+        if err != 0 {
+            unsafe { libusb_free_transfer(transfer) };
+            return Err(error_from_libusb(err).into());
+        }
+
+        let poller_completed = Arc::clone(&completed);
+        let poller = thread::spawn(move || {
+            let context = GlobalContext::default();
+            while poller_completed.load(Ordering::SeqCst) == 0 {
+                if context.handle_events(Some(EVENT_POLL_INTERVAL)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            transfer,
+            completed,
+            buffer,
+            cancel,
+            poller: Some(poller),
+        })
+    }
+
+    /// Reports whether the transfer has finished -- successfully, timed out,
+    /// errored, or cancelled -- without blocking. Doesn't pump libusb's event
+    /// loop itself; the background thread spawned in [`Self::submit`]
+    /// already does that, so this is just an atomic load.
+    pub fn poll(&self) -> bool {
+        self.completed.load(Ordering::SeqCst) != 0
+    }
+
+    /// Requests cancellation, same as calling `cancel()` on the
+    /// [`TransferHandle`] this was submitted with. The transfer doesn't
+    /// finish until libusb's background thread observes the resulting
+    /// `LIBUSB_TRANSFER_CANCELLED` completion, so [`Self::poll`] still needs
+    /// to go on reporting `false` until that happens.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+        unsafe { libusb_cancel_transfer(self.transfer) };
+    }
+
+    /// Blocks the calling thread until the transfer completes, `timeout`
+    /// elapses, or `self`'s [`TransferHandle`] is cancelled from elsewhere --
+    /// cancelling it itself once the deadline passes -- then returns the
+    /// data actually transferred.
+    pub fn wait(self, timeout: Duration) -> Result<Vec<u8>, UsbWasmError> {
+        let deadline = Instant::now() + timeout;
+        let mut cancel_requested = false;
+        while !self.poll() {
+            if !cancel_requested && (self.cancel.is_cancelled() || Instant::now() >= deadline) {
+                self.cancel();
+                cancel_requested = true;
+            }
+            thread::sleep(WAIT_POLL_INTERVAL);
+        }
+        self.finish()
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, UsbWasmError> {
+        if let Some(poller) = self.poller.take() {
+            let _ = poller.join();
+        }
+
+        let transfer_ref = unsafe { &*self.transfer };
+        let status = transfer_ref.status;
+        let actual_length = transfer_ref.actual_length;
+        unsafe { libusb_free_transfer(self.transfer) };
+
+        if status == LIBUSB_TRANSFER_CANCELLED {
+            return Err(if self.cancel.is_cancelled() {
+                UsbWasmError::Cancelled
+            } else {
+                UsbWasmError::Timeout
+            });
+        }
+        if status != LIBUSB_TRANSFER_COMPLETED {
+            // TODO: map the remaining libusb_transfer_status values (STALL,
+            // NO_DEVICE, OVERFLOW, ...) to more specific UsbWasmError
+            // variants, same as `submit_and_wait`.
+            return Err(UsbWasmError::Timeout);
+        }
+
+        self.buffer.truncate(actual_length as usize);
+        Ok(std::mem::take(&mut self.buffer))
+    }
+}
+
+pub fn submit_bulk_in(
+    handle: &DeviceHandle<GlobalContext>,
+    endpoint: u8,
+    buffer_size: usize,
+    cancel: TransferHandle,
+) -> Result<SubmittedTransfer, UsbWasmError> {
+    SubmittedTransfer::submit(
+        handle,
+        endpoint,
+        LIBUSB_TRANSFER_TYPE_BULK,
+        vec![0; buffer_size],
+        cancel,
+    )
+}
+
+pub fn submit_bulk_out(
+    handle: &DeviceHandle<GlobalContext>,
+    endpoint: u8,
+    data: &[u8],
+    cancel: TransferHandle,
+) -> Result<SubmittedTransfer, UsbWasmError> {
+    SubmittedTransfer::submit(
+        handle,
+        endpoint,
+        LIBUSB_TRANSFER_TYPE_BULK,
+        data.to_vec(),
+        cancel,
+    )
+}
+
+pub fn submit_interrupt_in(
+    handle: &DeviceHandle<GlobalContext>,
+    endpoint: u8,
+    buffer_size: usize,
+    cancel: TransferHandle,
+) -> Result<SubmittedTransfer, UsbWasmError> {
+    SubmittedTransfer::submit(
+        handle,
+        endpoint,
+        LIBUSB_TRANSFER_TYPE_INTERRUPT,
+        vec![0; buffer_size],
+        cancel,
+    )
+}
+
+pub fn submit_interrupt_out(
+    handle: &DeviceHandle<GlobalContext>,
+    endpoint: u8,
+    data: &[u8],
+    cancel: TransferHandle,
+) -> Result<SubmittedTransfer, UsbWasmError> {
+    SubmittedTransfer::submit(
+        handle,
+        endpoint,
+        LIBUSB_TRANSFER_TYPE_INTERRUPT,
+        data.to_vec(),
+        cancel,
+    )
+}
+
+pub fn bulk_transfer_in(
+    handle: &DeviceHandle<GlobalContext>,
+    endpoint: u8,
+    buffer_size: usize,
+    timeout: Duration,
+    cancel: &TransferHandle,
+) -> Result<Vec<u8>, UsbWasmError> {
+    submit_and_wait(
+        handle,
+        endpoint,
+        LIBUSB_TRANSFER_TYPE_BULK,
+        vec![0; buffer_size],
+        timeout,
+        cancel,
+    )
+}
+
+pub fn bulk_transfer_out(
+    handle: &DeviceHandle<GlobalContext>,
+    endpoint: u8,
+    data: &[u8],
+    timeout: Duration,
+    cancel: &TransferHandle,
+) -> Result<usize, UsbWasmError> {
+    let sent = submit_and_wait(
+        handle,
+        endpoint,
+        LIBUSB_TRANSFER_TYPE_BULK,
+        data.to_vec(),
+        timeout,
+        cancel,
+    )?;
+    Ok(sent.len())
+}
+
+pub fn interrupt_transfer_in(
+    handle: &DeviceHandle<GlobalContext>,
+    endpoint: u8,
+    buffer_size: usize,
+    timeout: Duration,
+    cancel: &TransferHandle,
+) -> Result<Vec<u8>, UsbWasmError> {
+    submit_and_wait(
+        handle,
+        endpoint,
+        LIBUSB_TRANSFER_TYPE_INTERRUPT,
+        vec![0; buffer_size],
+        timeout,
+        cancel,
+    )
+}
+
+pub fn interrupt_transfer_out(
+    handle: &DeviceHandle<GlobalContext>,
+    endpoint: u8,
+    data: &[u8],
+    timeout: Duration,
+    cancel: &TransferHandle,
+) -> Result<usize, UsbWasmError> {
+    let sent = submit_and_wait(
+        handle,
+        endpoint,
+        LIBUSB_TRANSFER_TYPE_INTERRUPT,
+        data.to_vec(),
+        timeout,
+        cancel,
+    )?;
+    Ok(sent.len())
+}
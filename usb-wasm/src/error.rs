@@ -6,4 +6,67 @@ pub enum UsbWasmError {
     RusbError(#[from] rusb::Error),
     #[error("device not opened")]
     DeviceNotOpened,
+    #[error("transfer timed out")]
+    Timeout,
+    #[error("transfer was cancelled")]
+    Cancelled,
+    #[error("this platform's libusb was built without hotplug support")]
+    HotplugUnsupported,
+}
+
+/// A coarse classification of [`UsbWasmError`], matching the categories a
+/// guest can reasonably be expected to recover from: `timeout`, `pipe`
+/// (stall/halt), `no-device`, `access`, `busy`, `overflow`, `io`, and the
+/// USBTMC-style notion of a transfer that's still `pending` or was
+/// explicitly `aborted`.
+///
+/// This is the payload the WIT world's `usb-error` variant should carry so
+/// `Host*` methods can return `result<_, usb-error>` instead of trapping the
+/// whole component on every failed transfer -- but that's a change to the
+/// `wadu436:usb/device` WIT interface, and this tree's `wit/deps/usb` isn't
+/// present to add it to. Landing this classification now means the
+/// `Host*` implementations in [`crate::host`] only need to swap their
+/// `.unwrap()`s for `?` once the WIT world catches up, instead of also
+/// having to work out what the variants should be.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbError {
+    #[error("transfer timed out")]
+    Timeout,
+    #[error("endpoint stalled")]
+    Pipe,
+    #[error("device was disconnected")]
+    NoDevice,
+    #[error("insufficient permissions")]
+    Access,
+    #[error("resource busy")]
+    Busy,
+    #[error("transfer overflowed the provided buffer")]
+    Overflow,
+    #[error("I/O error")]
+    Io,
+    #[error("transfer is still pending")]
+    Pending,
+    #[error("transfer was aborted")]
+    Aborted,
+    #[error("other error")]
+    Other,
+}
+
+impl From<&UsbWasmError> for UsbError {
+    fn from(err: &UsbWasmError) -> Self {
+        match err {
+            UsbWasmError::Timeout => UsbError::Timeout,
+            UsbWasmError::Cancelled => UsbError::Aborted,
+            UsbWasmError::DeviceNotOpened => UsbError::NoDevice,
+            UsbWasmError::HotplugUnsupported => UsbError::Other,
+            UsbWasmError::RusbError(rusb::Error::Timeout) => UsbError::Timeout,
+            UsbWasmError::RusbError(rusb::Error::Pipe) => UsbError::Pipe,
+            UsbWasmError::RusbError(rusb::Error::NoDevice) => UsbError::NoDevice,
+            UsbWasmError::RusbError(rusb::Error::Access) => UsbError::Access,
+            UsbWasmError::RusbError(rusb::Error::Busy) => UsbError::Busy,
+            UsbWasmError::RusbError(rusb::Error::Overflow) => UsbError::Overflow,
+            UsbWasmError::RusbError(rusb::Error::Io) => UsbError::Io,
+            UsbWasmError::RusbError(_) => UsbError::Other,
+        }
+    }
 }
@@ -1,5 +1,7 @@
+use tracing::{info, warn};
 use wasmtime_wasi::WasiView;
 
+use crate::policy::UsbPolicyHost;
 use crate::wadu436::usb::device::*;
 use crate::wadu436::usb::types::{ControlSetupRecipient, ControlSetupType};
 
@@ -26,12 +28,24 @@ fn host_control_setup_to_rusb(
     }
 }
 
-impl<T: WasiView> HostUsbDevice for T {
+impl<T: UsbPolicyHost> HostUsbDevice for T {
     fn enumerate(&mut self) -> wasmtime::Result<Vec<wasmtime::component::Resource<UsbDevice>>> {
+        let policy = self.usb_policy().clone();
         let table = self.table();
 
         Ok(UsbDevice::enumerate()?
             .into_iter()
+            .filter(|device| {
+                let allowed = policy.device_allowed(&device.descriptor);
+                if !allowed {
+                    warn!(
+                        vendor_id = device.descriptor.vendor_id,
+                        product_id = device.descriptor.product_id,
+                        "usb audit: enumerate hid a policy-denied device"
+                    );
+                }
+                allowed
+            })
             .map(|device| table.push(device))
             .collect::<Result<_, _>>()?)
     }
@@ -41,6 +55,7 @@ impl<T: WasiView> HostUsbDevice for T {
         &mut self,
         filter: Filter,
     ) -> wasmtime::Result<Option<wasmtime::component::Resource<UsbDevice>>> {
+        let policy = self.usb_policy().clone();
         let table = self.table();
         let device = UsbDevice::enumerate()?.into_iter().find(|device| {
             let descriptor = &device.descriptor;
@@ -61,7 +76,13 @@ impl<T: WasiView> HostUsbDevice for T {
             });
             let serial_number = filter.serial_number == descriptor.serial_number;
 
-            vendor_id && product_id && class_code && subclass_code && protocol_code && serial_number
+            vendor_id
+                && product_id
+                && class_code
+                && subclass_code
+                && protocol_code
+                && serial_number
+                && policy.device_allowed(descriptor)
         });
 
         Ok(device.map(|device| table.push(device)).transpose()?)
@@ -95,8 +116,22 @@ impl<T: WasiView> HostUsbDevice for T {
     }
 
     fn open(&mut self, rep: wasmtime::component::Resource<UsbDevice>) -> wasmtime::Result<()> {
+        let policy = self.usb_policy().clone();
         let device = self.table().get_mut(&rep)?;
+        if !policy.device_allowed(&device.descriptor) {
+            warn!(
+                vendor_id = device.descriptor.vendor_id,
+                product_id = device.descriptor.product_id,
+                "usb audit: denied open of policy-disallowed device"
+            );
+            return Err(wasmtime::Error::msg("device open denied by USB policy"));
+        }
         device.open()?;
+        info!(
+            vendor_id = device.descriptor.vendor_id,
+            product_id = device.descriptor.product_id,
+            "usb audit: device opened"
+        );
         Ok(())
     }
 
@@ -135,15 +170,34 @@ impl<T: WasiView> HostUsbDevice for T {
         rep: wasmtime::component::Resource<UsbDevice>,
         interface: wasmtime::component::Resource<UsbInterface>,
     ) -> wasmtime::Result<()> {
+        let policy = self.usb_policy().clone();
         let table = self.table();
         let interface = table.get(&interface)?;
         let interface_number = interface.descriptor.interface_number;
         let interface_setting = interface.descriptor.alternate_setting;
+        let interface_class = interface.descriptor.interface_class;
         let device = table.get_mut(&rep)?;
+
+        if !policy.interface_allowed(&device.descriptor, interface_class) {
+            warn!(
+                vendor_id = device.descriptor.vendor_id,
+                product_id = device.descriptor.product_id,
+                interface_class,
+                "usb audit: denied claim-interface on policy-disallowed interface"
+            );
+            return Err(wasmtime::Error::msg("interface claim denied by USB policy"));
+        }
+
         device.claim_interface(interface_number).unwrap();
         device
             .set_alternate_setting(interface_number, interface_setting)
             .unwrap();
+        info!(
+            vendor_id = device.descriptor.vendor_id,
+            product_id = device.descriptor.product_id,
+            interface_number,
+            "usb audit: interface claimed"
+        );
         Ok(())
     }
 
@@ -252,11 +306,15 @@ impl<T: WasiView> HostUsbDevice for T {
             };
         let buffer_size = ep.descriptor.max_packet_size;
         let device = table.get_mut(&rep)?;
-        let mut data = device
+        // The WIT interface only has room for a single packet per call, so
+        // num_packets stays 1 until that's extended, which also means the
+        // per-packet status this now tracks internally can't be surfaced to
+        // the guest yet.
+        let mut packets = device
             .iso_transfer_in(address, 1, buffer_size.into())
             .unwrap();
 
-        Ok(data.swap_remove(0))
+        Ok(packets.swap_remove(0).data)
     }
 
     fn write_isochronous(
@@ -273,7 +331,12 @@ impl<T: WasiView> HostUsbDevice for T {
                 crate::wadu436::usb::types::Direction::In => 0x80,
             };
         let device = table.get_mut(&rep)?;
-        let bytes_written = device.iso_transfer_out(address, &[data]).unwrap();
+        let length = data.len() as u64;
+        let statuses = device.iso_transfer_out(address, &[data]).unwrap();
+        let bytes_written = match statuses.first() {
+            Some(crate::IsoPacketStatus::Completed) => length,
+            _ => 0,
+        };
         Ok(bytes_written)
     }
 
@@ -315,6 +378,7 @@ impl<T: WasiView> HostUsbDevice for T {
         let table = self.table();
         let device = table.get_mut(&rep)?;
         let setup = host_control_setup_to_rusb(&request);
+        info!(request = request.request, length, "usb audit: control-in transfer");
         let data = device.control_transfer_in(setup, length).unwrap();
         Ok(data)
     }
@@ -328,6 +392,7 @@ impl<T: WasiView> HostUsbDevice for T {
         let table = self.table();
         let device = table.get_mut(&rep)?;
         let setup = host_control_setup_to_rusb(&request);
+        info!(request = request.request, len = data.len(), "usb audit: control-out transfer");
         let bytes_written = device.control_transfer_out(setup, &data).unwrap();
         Ok(bytes_written as _)
     }
@@ -0,0 +1,151 @@
+//! Arrival/removal notifications for long-running embedders, backed by
+//! `rusb`'s hotplug callback API rather than re-`enumerate()`-ing on a
+//! timer.
+//!
+//! There's no WIT world here to add a guest-facing `watch()`/pollable
+//! method to (see [`crate::error::UsbError`]'s doc comment for why), so
+//! this is a host-only API: [`watch`] returns a [`Watcher`] the embedder
+//! polls directly, the same way [`crate::export_usbip`] and
+//! [`crate::async_transfer`] are host-only capabilities layered on top of
+//! the WIT-exposed [`crate::UsbDevice`].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rusb::{Device, GlobalContext, Hotplug, HotplugBuilder, UsbContext};
+
+use crate::error::UsbWasmError;
+use crate::UsbDevice;
+
+/// How often the background poller wakes up to give libusb a chance to
+/// deliver queued hotplug callbacks.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A single arrival or removal, as reported by [`Watcher::next`].
+pub enum HotplugEvent {
+    /// A matching device showed up, enumerated (not opened) the same way
+    /// [`UsbDevice::enumerate`] would.
+    Arrived(UsbDevice),
+    /// A matching device disappeared. Unlike `Arrived`, there's nothing
+    /// left to open by the time libusb tells us this -- only the
+    /// identifiers it cached when the device was still present.
+    Left { vendor_id: u16, product_id: u16 },
+}
+
+/// Which devices a [`Watcher`] should notify about. Deliberately smaller
+/// than the WIT `Filter` struct [`crate::host`]'s `request_device` accepts
+/// (no class/subclass/protocol/serial filtering) since a `Left` event only
+/// ever carries vendor/product IDs -- filtering on more than that would
+/// silently never fire for removals.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HotplugFilter {
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+}
+
+impl HotplugFilter {
+    fn matches(&self, vendor_id: u16, product_id: u16) -> bool {
+        self.vendor_id.map_or(true, |want| want == vendor_id)
+            && self.product_id.map_or(true, |want| want == product_id)
+    }
+}
+
+struct Callback {
+    filter: HotplugFilter,
+    events: Sender<HotplugEvent>,
+}
+
+impl Hotplug<GlobalContext> for Callback {
+    fn device_arrived(&mut self, device: Device<GlobalContext>) {
+        let Ok(descriptor) = device.device_descriptor() else {
+            return;
+        };
+        if !self
+            .filter
+            .matches(descriptor.vendor_id(), descriptor.product_id())
+        {
+            return;
+        }
+        if let Ok(device) = UsbDevice::from_rusb_device(device) {
+            let _ = self.events.send(HotplugEvent::Arrived(device));
+        }
+    }
+
+    fn device_left(&mut self, device: Device<GlobalContext>) {
+        let Ok(descriptor) = device.device_descriptor() else {
+            return;
+        };
+        let (vendor_id, product_id) = (descriptor.vendor_id(), descriptor.product_id());
+        if self.filter.matches(vendor_id, product_id) {
+            let _ = self.events.send(HotplugEvent::Left {
+                vendor_id,
+                product_id,
+            });
+        }
+    }
+}
+
+/// A live hotplug subscription. Dropping it stops the background poller
+/// and deregisters the libusb callback.
+pub struct Watcher {
+    events: Receiver<HotplugEvent>,
+    running: Arc<AtomicBool>,
+    _registration: rusb::Registration<GlobalContext>,
+    _poller: JoinHandle<()>,
+}
+
+impl Watcher {
+    /// Blocks until a matching device arrives or leaves, or `timeout`
+    /// elapses, in which case `None` is returned.
+    pub fn next(&self, timeout: Duration) -> Option<HotplugEvent> {
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Starts watching for devices matching `filter` arriving or leaving.
+/// Already-connected devices are not reported; call
+/// [`UsbDevice::enumerate`] first if the caller also needs the current
+/// snapshot.
+pub fn watch(filter: HotplugFilter) -> Result<Watcher, UsbWasmError> {
+    if !rusb::has_hotplug() {
+        return Err(UsbWasmError::HotplugUnsupported);
+    }
+
+    let (sender, events) = channel();
+    let registration = HotplugBuilder::new().enumerate(false).register(
+        GlobalContext::default(),
+        Box::new(Callback {
+            filter,
+            events: sender,
+        }),
+    )?;
+
+    let running = Arc::new(AtomicBool::new(true));
+    let poller = {
+        let running = Arc::clone(&running);
+        thread::spawn(move || {
+            while running.load(Ordering::SeqCst) {
+                let _ = GlobalContext::default().handle_events(Some(POLL_INTERVAL));
+            }
+        })
+    };
+
+    Ok(Watcher {
+        events,
+        running,
+        _registration: registration,
+        _poller: poller,
+    })
+}
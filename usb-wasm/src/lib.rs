@@ -11,6 +11,7 @@ wasmtime::component::bindgen!({
 });
 
 use error::UsbWasmError;
+pub use error::UsbError;
 use rusb::{
     constants::LIBUSB_TRANSFER_TYPE_ISOCHRONOUS,
     ffi::{libusb_alloc_transfer, libusb_handle_events_completed, libusb_submit_transfer},
@@ -21,8 +22,17 @@ use wadu436::usb::{self, types::Direction};
 
 use wasmtime_wasi::WasiView;
 
+mod async_transfer;
 mod error;
 mod host;
+mod hotplug;
+mod policy;
+mod usbip;
+
+pub use async_transfer::{SubmittedTransfer, TransferHandle};
+pub use hotplug::{watch, HotplugEvent, HotplugFilter, Watcher};
+pub use policy::{UsbPolicy, UsbPolicyHost, UsbPolicyRule};
+pub use usbip::{export as export_usbip, UsbIpError};
 
 const TIMEOUT: Duration = Duration::from_secs(1);
 
@@ -41,6 +51,41 @@ pub struct ControlSetup {
     pub index: u16,
 }
 
+/// libusb's `enum libusb_transfer_status` values (libusb/libusb.h),
+/// reused verbatim for each iso packet's `iso_packet_desc[i].status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsoPacketStatus {
+    Completed,
+    Error,
+    TimedOut,
+    Cancelled,
+    Stall,
+    NoDevice,
+    Overflow,
+}
+
+impl IsoPacketStatus {
+    fn from_libusb(status: i32) -> Self {
+        match status {
+            0 => Self::Completed,
+            2 => Self::TimedOut,
+            3 => Self::Cancelled,
+            4 => Self::Stall,
+            5 => Self::NoDevice,
+            6 => Self::Overflow,
+            _ => Self::Error,
+        }
+    }
+}
+
+/// One packet of a multi-packet isochronous read: the data actually
+/// transferred (empty unless `status` is [`IsoPacketStatus::Completed`])
+/// alongside the per-packet status libusb reported for it.
+pub struct IsoPacket {
+    pub data: Vec<u8>,
+    pub status: IsoPacketStatus,
+}
+
 fn error_from_libusb(err: i32) -> rusb::Error {
     match err {
         rusb::ffi::constants::LIBUSB_ERROR_IO => rusb::Error::Io,
@@ -71,60 +116,69 @@ impl UsbDevice {
         let devices = rusb::devices()?;
 
         let mut devices_ = Vec::with_capacity(devices.len());
-
         for device in devices.iter() {
-            let handle = device.open()?;
-
-            // First get all the information needed to apply the filters
-            let descriptor = device.device_descriptor()?;
-            let language = handle.read_languages(TIMEOUT)?[0];
-
-            let product_name = handle
-                .read_product_string(language, &descriptor, TIMEOUT)
-                .ok();
-            let manufacturer_name = handle
-                .read_manufacturer_string(language, &descriptor, TIMEOUT)
-                .ok();
-            let serial_number = handle
-                .read_serial_number_string(language, &descriptor, TIMEOUT)
-                .ok();
-
-            let device_version = descriptor.device_version();
-            let usb_version = descriptor.usb_version();
-
-            let descriptor = usb::device::DeviceDescriptor {
-                vendor_id: descriptor.vendor_id(),
-                product_id: descriptor.product_id(),
-                device_class: descriptor.class_code(),
-                device_subclass: descriptor.sub_class_code(),
-                device_protocol: descriptor.protocol_code(),
-                manufacturer_name,
-                product_name,
-                serial_number,
-                device_version: (
-                    device_version.major(),
-                    device_version.minor(),
-                    device_version.sub_minor(),
-                ),
-                usb_version: (
-                    usb_version.major(),
-                    usb_version.minor(),
-                    usb_version.sub_minor(),
-                ),
-                max_packet_size: descriptor.max_packet_size(),
-            };
-
-            devices_.push(UsbDevice {
-                device,
-                handle: None,
-                language,
-                descriptor,
-            });
+            devices_.push(Self::from_rusb_device(device)?);
         }
 
         Ok(devices_)
     }
 
+    /// Builds a [`UsbDevice`] from a `rusb` device we haven't seen before,
+    /// reading its string descriptors the same way [`Self::enumerate`] does
+    /// for every device it finds. Also used by [`crate::hotplug`] to turn a
+    /// newly-arrived device into a resource a guest can hold onto.
+    pub(crate) fn from_rusb_device(
+        device: rusb::Device<rusb::GlobalContext>,
+    ) -> Result<Self, UsbWasmError> {
+        let handle = device.open()?;
+
+        // First get all the information needed to apply the filters
+        let descriptor = device.device_descriptor()?;
+        let language = handle.read_languages(TIMEOUT)?[0];
+
+        let product_name = handle
+            .read_product_string(language, &descriptor, TIMEOUT)
+            .ok();
+        let manufacturer_name = handle
+            .read_manufacturer_string(language, &descriptor, TIMEOUT)
+            .ok();
+        let serial_number = handle
+            .read_serial_number_string(language, &descriptor, TIMEOUT)
+            .ok();
+
+        let device_version = descriptor.device_version();
+        let usb_version = descriptor.usb_version();
+
+        let descriptor = usb::device::DeviceDescriptor {
+            vendor_id: descriptor.vendor_id(),
+            product_id: descriptor.product_id(),
+            device_class: descriptor.class_code(),
+            device_subclass: descriptor.sub_class_code(),
+            device_protocol: descriptor.protocol_code(),
+            manufacturer_name,
+            product_name,
+            serial_number,
+            device_version: (
+                device_version.major(),
+                device_version.minor(),
+                device_version.sub_minor(),
+            ),
+            usb_version: (
+                usb_version.major(),
+                usb_version.minor(),
+                usb_version.sub_minor(),
+            ),
+            max_packet_size: descriptor.max_packet_size(),
+        };
+
+        Ok(UsbDevice {
+            device,
+            handle: None,
+            language,
+            descriptor,
+        })
+    }
+
     pub fn open(&mut self) -> Result<(), UsbWasmError> {
         self.handle = Some(self.device.open()?);
         Ok(())
@@ -287,12 +341,144 @@ impl UsbDevice {
         }
     }
 
+    /// Like [`Self::bulk_transfer_in`], but instead of blocking on `rusb`'s
+    /// hardcoded 1-second [`TIMEOUT`] until the device responds, gives up
+    /// after `timeout` or as soon as `cancel` is triggered, whichever comes
+    /// first. See [`async_transfer`] for how that's driven.
+    pub fn bulk_transfer_in_cancellable(
+        &mut self,
+        endpoint: u8,
+        buffer_size: usize,
+        timeout: Duration,
+        cancel: &async_transfer::TransferHandle,
+    ) -> Result<Vec<u8>, UsbWasmError> {
+        if let Some(handle) = &self.handle {
+            async_transfer::bulk_transfer_in(handle, endpoint, buffer_size, timeout, cancel)
+        } else {
+            Err(UsbWasmError::DeviceNotOpened)
+        }
+    }
+
+    /// Cancellable/timed-out counterpart to [`Self::bulk_transfer_out`]; see
+    /// [`Self::bulk_transfer_in_cancellable`].
+    pub fn bulk_transfer_out_cancellable(
+        &mut self,
+        endpoint: u8,
+        buffer: &[u8],
+        timeout: Duration,
+        cancel: &async_transfer::TransferHandle,
+    ) -> Result<usize, UsbWasmError> {
+        if let Some(handle) = &self.handle {
+            async_transfer::bulk_transfer_out(handle, endpoint, buffer, timeout, cancel)
+        } else {
+            Err(UsbWasmError::DeviceNotOpened)
+        }
+    }
+
+    /// Cancellable/timed-out counterpart to [`Self::interrupt_transfer_in`];
+    /// see [`Self::bulk_transfer_in_cancellable`].
+    pub fn interrupt_transfer_in_cancellable(
+        &mut self,
+        endpoint: u8,
+        buffer_size: usize,
+        timeout: Duration,
+        cancel: &async_transfer::TransferHandle,
+    ) -> Result<Vec<u8>, UsbWasmError> {
+        if let Some(handle) = &self.handle {
+            async_transfer::interrupt_transfer_in(handle, endpoint, buffer_size, timeout, cancel)
+        } else {
+            Err(UsbWasmError::DeviceNotOpened)
+        }
+    }
+
+    /// Cancellable/timed-out counterpart to [`Self::interrupt_transfer_out`];
+    /// see [`Self::bulk_transfer_in_cancellable`].
+    pub fn interrupt_transfer_out_cancellable(
+        &mut self,
+        endpoint: u8,
+        buffer: &[u8],
+        timeout: Duration,
+        cancel: &async_transfer::TransferHandle,
+    ) -> Result<usize, UsbWasmError> {
+        if let Some(handle) = &self.handle {
+            async_transfer::interrupt_transfer_out(handle, endpoint, buffer, timeout, cancel)
+        } else {
+            Err(UsbWasmError::DeviceNotOpened)
+        }
+    }
+
+    /// Submits a bulk transfer without waiting for it, returning a
+    /// [`SubmittedTransfer`] the caller can [`SubmittedTransfer::poll`]
+    /// without blocking instead of committing up front to
+    /// [`Self::bulk_transfer_in_cancellable`]'s wait. Call
+    /// [`SubmittedTransfer::wait`] with a timeout to enforce a deadline and
+    /// get the data back once it's done.
+    pub fn submit_bulk_transfer_in(
+        &mut self,
+        endpoint: u8,
+        buffer_size: usize,
+        cancel: async_transfer::TransferHandle,
+    ) -> Result<async_transfer::SubmittedTransfer, UsbWasmError> {
+        if let Some(handle) = &self.handle {
+            async_transfer::submit_bulk_in(handle, endpoint, buffer_size, cancel)
+        } else {
+            Err(UsbWasmError::DeviceNotOpened)
+        }
+    }
+
+    /// Non-blocking counterpart to [`Self::bulk_transfer_out_cancellable`];
+    /// see [`Self::submit_bulk_transfer_in`].
+    pub fn submit_bulk_transfer_out(
+        &mut self,
+        endpoint: u8,
+        buffer: &[u8],
+        cancel: async_transfer::TransferHandle,
+    ) -> Result<async_transfer::SubmittedTransfer, UsbWasmError> {
+        if let Some(handle) = &self.handle {
+            async_transfer::submit_bulk_out(handle, endpoint, buffer, cancel)
+        } else {
+            Err(UsbWasmError::DeviceNotOpened)
+        }
+    }
+
+    /// Non-blocking counterpart to
+    /// [`Self::interrupt_transfer_in_cancellable`]; see
+    /// [`Self::submit_bulk_transfer_in`].
+    pub fn submit_interrupt_transfer_in(
+        &mut self,
+        endpoint: u8,
+        buffer_size: usize,
+        cancel: async_transfer::TransferHandle,
+    ) -> Result<async_transfer::SubmittedTransfer, UsbWasmError> {
+        if let Some(handle) = &self.handle {
+            async_transfer::submit_interrupt_in(handle, endpoint, buffer_size, cancel)
+        } else {
+            Err(UsbWasmError::DeviceNotOpened)
+        }
+    }
+
+    /// Non-blocking counterpart to
+    /// [`Self::interrupt_transfer_out_cancellable`]; see
+    /// [`Self::submit_bulk_transfer_in`].
+    pub fn submit_interrupt_transfer_out(
+        &mut self,
+        endpoint: u8,
+        buffer: &[u8],
+        cancel: async_transfer::TransferHandle,
+    ) -> Result<async_transfer::SubmittedTransfer, UsbWasmError> {
+        if let Some(handle) = &self.handle {
+            async_transfer::submit_interrupt_out(handle, endpoint, buffer, cancel)
+        } else {
+            Err(UsbWasmError::DeviceNotOpened)
+        }
+    }
+
     pub fn iso_transfer_in(
         &mut self,
         endpoint: u8,
         num_packets: i32,
         buffer_size: usize,
-    ) -> Result<Vec<Vec<u8>>, UsbWasmError> {
+    ) -> Result<Vec<IsoPacket>, UsbWasmError> {
         if num_packets < 0 {
             // Error
             return Err(rusb::Error::InvalidParam.into());
@@ -338,22 +524,19 @@ impl UsbDevice {
                 return Err(error_from_libusb(err).into());
             }
 
-            let mut output_data = Vec::with_capacity(num_packets as usize);
+            let mut packets = Vec::with_capacity(num_packets as usize);
             for i in 0..num_packets as usize {
-                let entry = unsafe { (*transfer).iso_packet_desc.get_unchecked_mut(0) };
-                if entry.status == 0 {
-                    output_data.push(
-                        buffer[i * buffer_size..i * buffer_size + entry.actual_length as usize]
-                            .to_vec(),
-                    );
+                let entry = unsafe { (*transfer).iso_packet_desc.get_unchecked_mut(i) };
+                let status = IsoPacketStatus::from_libusb(entry.status);
+                let data = if status == IsoPacketStatus::Completed {
+                    buffer[i * buffer_size..i * buffer_size + entry.actual_length as usize].to_vec()
                 } else {
-                    // TODO: handle errors here
-                    // Status code meanings
-                    // https://libusb.sourceforge.io/api-1.0/group__libusb__asyncio.html#ga9fcb2aa23d342060ebda1d0cf7478856
-                }
+                    Vec::new()
+                };
+                packets.push(IsoPacket { data, status });
             }
 
-            Ok(output_data)
+            Ok(packets)
         } else {
             Err(UsbWasmError::DeviceNotOpened)
         }
@@ -363,9 +546,10 @@ impl UsbDevice {
         &mut self,
         endpoint: u8,
         buffers: &[Vec<u8>],
-    ) -> Result<u64, UsbWasmError> {
+    ) -> Result<Vec<IsoPacketStatus>, UsbWasmError> {
         if let Some(handle) = &mut self.handle {
-            let transfer = unsafe { libusb_alloc_transfer(1) };
+            let num_packets = buffers.len() as i32;
+            let transfer = unsafe { libusb_alloc_transfer(num_packets) };
             let transfer_ref = unsafe { &mut *transfer };
 
             let mut completed = 0_i32;
@@ -380,7 +564,7 @@ impl UsbDevice {
             transfer_ref.timeout = 1000;
             transfer_ref.buffer = buffer.as_ptr() as *mut _;
             transfer_ref.length = buffer.len() as _;
-            transfer_ref.num_iso_packets = 1;
+            transfer_ref.num_iso_packets = num_packets;
             // It should be okay to pass in this (stack) variable, as this function will not return untill after the transfer is complete.
             transfer_ref.user_data = completed_ptr as *mut _;
 
@@ -408,25 +592,42 @@ impl UsbDevice {
                 return Err(error_from_libusb(err).into());
             }
 
-            let mut bytes_written: u64 = 0;
+            let mut statuses = Vec::with_capacity(buffers.len());
             for i in 0..buffers.len() {
                 let entry = unsafe { (*transfer).iso_packet_desc.get_unchecked_mut(i) };
-                if entry.status == 0 {
-                    bytes_written += entry.actual_length as u64;
-                } else {
-                    // TODO: handle errors here
-                    // Status code meanings
-                    // https://libusb.sourceforge.io/api-1.0/group__libusb__asyncio.html#ga9fcb2aa23d342060ebda1d0cf7478856
-                }
+                statuses.push(IsoPacketStatus::from_libusb(entry.status));
             }
 
-            Ok(bytes_written)
+            Ok(statuses)
         } else {
             // TODO: fix a proper error here
             Err(UsbWasmError::DeviceNotOpened)
         }
     }
 
+    /// Convenience alias for [`Self::iso_transfer_in`], named to match the
+    /// WIT-facing `read-isochronous` method it's the multi-packet
+    /// counterpart of.
+    pub fn read_isochronous(
+        &mut self,
+        endpoint: u8,
+        num_packets: i32,
+        buffer_size: usize,
+    ) -> Result<Vec<IsoPacket>, UsbWasmError> {
+        self.iso_transfer_in(endpoint, num_packets, buffer_size)
+    }
+
+    /// Convenience alias for [`Self::iso_transfer_out`], named to match the
+    /// WIT-facing `write-isochronous` method it's the multi-packet
+    /// counterpart of.
+    pub fn write_isochronous(
+        &mut self,
+        endpoint: u8,
+        buffers: &[Vec<u8>],
+    ) -> Result<Vec<IsoPacketStatus>, UsbWasmError> {
+        self.iso_transfer_out(endpoint, buffers)
+    }
+
     pub fn control_transfer_in(
         &mut self,
         setup: ControlSetup,
@@ -633,7 +834,7 @@ impl std::fmt::Display for UsbError {
 
 impl Error for UsbError {}
 
-pub fn add_to_linker<T: WasiView>(
+pub fn add_to_linker<T: UsbPolicyHost>(
     linker: &mut wasmtime::component::Linker<T>,
 ) -> wasmtime::Result<()> {
     wadu436::usb::device::add_to_linker(linker, |s| s)
@@ -0,0 +1,82 @@
+use wasmtime_wasi::WasiView;
+
+use crate::wadu436::usb::device::DeviceDescriptor;
+
+/// Access-control policy applied to every USB device the guest tries to
+/// enumerate, open, or claim an interface on.
+///
+/// A device is allowed if it matches at least one entry in `allow` (or
+/// `allow` is empty, meaning "allow everything not explicitly denied") and
+/// does not match any entry in `deny`. `deny` always wins over `allow`.
+#[derive(Debug, Default, Clone)]
+pub struct UsbPolicy {
+    pub allow: Vec<UsbPolicyRule>,
+    pub deny: Vec<UsbPolicyRule>,
+}
+
+/// A single allow/deny rule. `None` fields are wildcards.
+#[derive(Debug, Default, Clone)]
+pub struct UsbPolicyRule {
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub device_class: Option<u8>,
+    pub interface_class: Option<u8>,
+}
+
+impl UsbPolicyRule {
+    fn matches_device(&self, descriptor: &DeviceDescriptor) -> bool {
+        self.vendor_id.map_or(true, |v| v == descriptor.vendor_id)
+            && self
+                .product_id
+                .map_or(true, |p| p == descriptor.product_id)
+            && self
+                .device_class
+                .map_or(true, |c| c == descriptor.device_class)
+    }
+
+    fn matches_interface(&self, interface_class: u8) -> bool {
+        self.interface_class
+            .map_or(true, |c| c == interface_class)
+    }
+}
+
+impl UsbPolicy {
+    /// A policy that allows every device. This is the default, matching the
+    /// crate's previous unrestricted behavior.
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    pub fn device_allowed(&self, descriptor: &DeviceDescriptor) -> bool {
+        let denied = self.deny.iter().any(|rule| rule.matches_device(descriptor));
+        if denied {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|rule| rule.matches_device(descriptor))
+    }
+
+    pub fn interface_allowed(&self, descriptor: &DeviceDescriptor, interface_class: u8) -> bool {
+        if !self.device_allowed(descriptor) {
+            return false;
+        }
+        let denied = self
+            .deny
+            .iter()
+            .any(|rule| rule.matches_device(descriptor) && rule.matches_interface(interface_class));
+        if denied {
+            return false;
+        }
+        self.allow.is_empty()
+            || self
+                .allow
+                .iter()
+                .any(|rule| rule.matches_device(descriptor) && rule.matches_interface(interface_class))
+    }
+}
+
+/// Implemented by host states that want USB access gated by a [`UsbPolicy`].
+/// `usb_wasm::add_to_linker` requires this instead of plain `WasiView` so the
+/// guest can never bypass the policy by construction.
+pub trait UsbPolicyHost: WasiView {
+    fn usb_policy(&self) -> &UsbPolicy;
+}